@@ -0,0 +1,129 @@
+//! Timings for the intcode machine on the puzzle programs that run it hardest.
+//!
+//! Run with `cargo bench --bench intcode`. The timings are followed by a
+//! profile of the day 13 game, to show where the machine spends its time.
+use common::int_code_machine::{Machine, Status};
+use common::permutations::*;
+use std::time::{Duration, Instant};
+
+const DAY2: &str = include_str!("../day2/src/input/input1");
+const DAY7: &str = include_str!("../day7/src/input/input");
+const DAY9: &str = include_str!("../day9/src/input/input");
+const DAY13: &str = include_str!("../day13/src/input/input");
+
+fn main() {
+    bench("day 2 noun/verb sweep", 15, day2_sweep);
+    bench("day 2 sweep, budgeted", 15, day2_sweep_budgeted);
+    bench("day 2 sweep, transpiled", 15, day2_sweep_transpiled);
+    bench("day 7 feedback loops", 60, day7_feedback);
+    bench("day 9 BOOST sensor", 15, day9_boost);
+    bench("day 13 initial screen", 200, day13_screen);
+    bench("day 13 game", 5, || day13::final_score(DAY13));
+
+    let profile = day13::profile_game(DAY13);
+    println!("\n------ day 13 game profile ------\n{}", profile);
+}
+
+fn bench(name: &str, iterations: usize, f: fn() -> i128) {
+    // warm up, and keep the result alive so the work isn't optimised out
+    let mut check = f();
+    let mut times = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            check ^= f();
+            start.elapsed()
+        })
+        .collect::<Vec<Duration>>();
+    times.sort();
+    println!(
+        "{:<24} min {:>10.3}ms  median {:>10.3}ms  ({})",
+        name,
+        times[0].as_secs_f64() * 1000.0,
+        times[times.len() / 2].as_secs_f64() * 1000.0,
+        check & 1
+    );
+}
+
+/// every one of the 10,000 noun/verb pairs, parsing the program each time as
+/// day 2 does
+fn day2_sweep() -> i128 {
+    let mut total = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut machine = Machine::new(DAY2, vec![]);
+            machine.memory[1] = noun;
+            machine.memory[2] = verb;
+            if machine.try_run().is_ok() {
+                total += machine.memory[0];
+            }
+        }
+    }
+    total
+}
+
+/// the sweep through day 2's own interpreted path, with the instruction
+/// budget the transpiled sweep has too, for a like-for-like comparison
+fn day2_sweep_budgeted() -> i128 {
+    let mut total = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            total += day2::with_first_registers::<i128>(DAY2, noun, verb).unwrap_or(0);
+        }
+    }
+    total
+}
+
+/// the same sweep with the program transpiled to Rust at build time
+fn day2_sweep_transpiled() -> i128 {
+    let mut total = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            total += day2::with_first_registers_transpiled(noun, verb).unwrap_or(0);
+        }
+    }
+    total
+}
+
+/// all 120 phase permutations of the five amplifier feedback loop
+fn day7_feedback() -> i128 {
+    let start = Machine::new(DAY7, vec![]);
+    let mut best = 0;
+    (5..10).collect::<Vec<i128>>().permutations().for_each(|phases| {
+        let mut amps = phases
+            .iter()
+            .map(|&phase| {
+                let mut amp = start.clone();
+                amp.wait_on_input();
+                amp.add_input(phase);
+                amp
+            })
+            .collect::<Vec<_>>();
+        let mut signal = 0;
+        loop {
+            let mut status = Status::Waiting;
+            for amp in amps.iter_mut() {
+                amp.add_input(signal);
+                status = amp.run();
+                signal = *amp.output.last().unwrap();
+            }
+            // the loop is done once the last amplifier halts
+            if let Status::Halted = status {
+                break;
+            }
+        }
+        best = best.max(signal);
+    });
+    best
+}
+
+fn day9_boost() -> i128 {
+    let mut machine = Machine::new(DAY9, vec![2]);
+    machine.run();
+    machine.output[0]
+}
+
+fn day13_screen() -> i128 {
+    let mut machine = Machine::new(DAY13, vec![]);
+    machine.run();
+    machine.output.len() as i128
+}
@@ -1,72 +1,192 @@
 use crate::digits::*;
-use std::cmp::max;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::time::{Duration, Instant};
 
-#[derive(Copy, Clone)]
-enum ParameterMode {
+pub mod ascii;
+pub mod assembler;
+pub mod compiler;
+pub mod control_flow;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
+pub mod history;
+pub mod hooks;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod optimizer;
+pub mod profile;
+pub mod protection;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
+pub mod transpiler;
+pub mod word;
+
+use history::{History, Recording};
+use hooks::Hooks;
+use io::BufferedInput;
+pub use io::{InputSource, OutputSink};
+pub use memory::{Memory, PagedMemory};
+pub use protection::SelfModification;
+use protection::{Protection, SelfModificationWatch};
+pub use word::{Arithmetic, Word};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParameterMode {
     Positional,
     Immediate,
     Relative,
 }
 
-struct Parameter {
-    value: i128,
-    mode: ParameterMode,
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Parameter<W = i128> {
+    pub value: W,
+    pub mode: ParameterMode,
 }
 
-impl Parameter {
-    fn new(value: i128, mode: ParameterMode) -> Self {
+impl<W> Parameter<W> {
+    fn new(value: W, mode: ParameterMode) -> Self {
         Parameter { value, mode }
     }
 }
 
-enum Instruction {
-    Add(Parameter, Parameter, Parameter),
-    Mult(Parameter, Parameter, Parameter),
-    Input(Parameter),
-    Output(Parameter),
-    JumpTrue(Parameter, Parameter),
-    JumpFalse(Parameter, Parameter),
-    LessThan(Parameter, Parameter, Parameter),
-    Equal(Parameter, Parameter, Parameter),
-    AdjustRelativeBase(Parameter),
+/// Positional parameters are written bare, immediate ones as `#value` and
+/// relative ones as `@offset`
+impl<W: fmt::Display> fmt::Display for Parameter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            ParameterMode::Positional => write!(f, "{}", self.value),
+            ParameterMode::Immediate => write!(f, "#{}", self.value),
+            ParameterMode::Relative => write!(f, "@{}", self.value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction<W = i128> {
+    Add(Parameter<W>, Parameter<W>, Parameter<W>),
+    Mult(Parameter<W>, Parameter<W>, Parameter<W>),
+    Input(Parameter<W>),
+    Output(Parameter<W>),
+    JumpTrue(Parameter<W>, Parameter<W>),
+    JumpFalse(Parameter<W>, Parameter<W>),
+    LessThan(Parameter<W>, Parameter<W>, Parameter<W>),
+    Equal(Parameter<W>, Parameter<W>, Parameter<W>),
+    AdjustRelativeBase(Parameter<W>),
     Halt,
 }
 
-impl Instruction {
-    fn size(&self) -> usize {
+impl<W: Word> Instruction<W> {
+    /// the number of memory words the instruction occupies, including the opcode
+    pub fn size(&self) -> usize {
         use Instruction::*;
         match self {
             Add { .. } | Mult { .. } | LessThan { .. } | Equal { .. } => 4,
             Input { .. } | Output { .. } | AdjustRelativeBase { .. } => 2,
             JumpFalse { .. } | JumpTrue { .. } => 3,
-            Halt => 0,
+            Halt => 1,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            Add(..) => "ADD",
+            Mult(..) => "MUL",
+            Input(..) => "IN",
+            Output(..) => "OUT",
+            JumpTrue(..) => "JT",
+            JumpFalse(..) => "JF",
+            LessThan(..) => "LT",
+            Equal(..) => "EQ",
+            AdjustRelativeBase(..) => "ARB",
+            Halt => "HLT",
+        }
+    }
+
+    /// the instruction's parameters, in the order they appear in memory
+    pub fn parameters(&self) -> Vec<Parameter<W>> {
+        use Instruction::*;
+        match self {
+            Add(a, b, c) | Mult(a, b, c) | LessThan(a, b, c) | Equal(a, b, c) => {
+                vec![a.clone(), b.clone(), c.clone()]
+            }
+            JumpTrue(a, b) | JumpFalse(a, b) => vec![a.clone(), b.clone()],
+            Input(a) | Output(a) | AdjustRelativeBase(a) => vec![a.clone()],
+            Halt => vec![],
+        }
+    }
+
+    /// the memory words for this instruction, the inverse of `decode`
+    pub fn encode(&self) -> Vec<W> {
+        use Instruction::*;
+        let opcode = match self {
+            Add(..) => 1,
+            Mult(..) => 2,
+            Input(..) => 3,
+            Output(..) => 4,
+            JumpTrue(..) => 5,
+            JumpFalse(..) => 6,
+            LessThan(..) => 7,
+            Equal(..) => 8,
+            AdjustRelativeBase(..) => 9,
+            Halt => 99,
+        };
+        let parameters = self.parameters();
+        let modes = parameters.iter().rev().fold(0, |modes, parameter| {
+            let mode = match parameter.mode {
+                ParameterMode::Positional => 0,
+                ParameterMode::Immediate => 1,
+                ParameterMode::Relative => 2,
+            };
+            modes * 10 + mode
+        });
+        let mut words = vec![W::from_usize(modes * 100 + opcode)];
+        words.extend(parameters.into_iter().map(|p| p.value));
+        words
+    }
+}
+
+impl<W: Word> fmt::Display for Instruction<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (i, parameter) in self.parameters().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, parameter)?;
         }
+        Ok(())
     }
 }
 
-fn get_or_else<T>(src: &Vec<T>, index: usize, default: T) -> T
+fn get_or_else<T>(src: &[T], index: usize, default: T) -> T
 where
-    T: Copy,
+    T: Clone,
 {
     if src.len() > index {
-        src[index]
+        src[index].clone()
     } else {
         default
     }
 }
 
-fn get_parameters(
-    src: &[i128],
-    modes: &Vec<ParameterMode>,
-    count: usize,
-) -> (Option<Parameter>, Option<Parameter>, Option<Parameter>) {
+type Parameters<W> = (
+    Option<Parameter<W>>,
+    Option<Parameter<W>>,
+    Option<Parameter<W>>,
+);
+
+fn get_parameters<W: Word>(src: &[W], modes: &[ParameterMode], count: usize) -> Parameters<W> {
+    // words past the end of `src` read as 0, just like unallocated memory
     let p1 = Some(Parameter::new(
-        src[0],
+        get_or_else(src, 0, W::zero()),
         get_or_else(modes, 0, ParameterMode::Positional),
     ));
     let p2 = if count > 1 {
         Some(Parameter::new(
-            src[1],
+            get_or_else(src, 1, W::zero()),
             get_or_else(modes, 1, ParameterMode::Positional),
         ))
     } else {
@@ -74,7 +194,7 @@ fn get_parameters(
     };
     let p3 = if count > 2 {
         Some(Parameter::new(
-            src[2],
+            get_or_else(src, 2, W::zero()),
             get_or_else(modes, 2, ParameterMode::Positional),
         ))
     } else {
@@ -83,98 +203,514 @@ fn get_parameters(
     (p1, p2, p3)
 }
 
-impl Instruction {
-    fn decode(mem: &[i128]) -> Instruction {
-        use Instruction::*;
+impl<W: Word> Instruction<W> {
+    /// decode the instruction at the start of `mem`, which lives at `address`
+    pub fn decode(mem: &[W], address: usize) -> Result<Instruction<W>, MachineError<W>> {
+        let opcode = Opcode::decode(&get_or_else(mem, 0, W::zero()), address)?;
+        Ok(opcode.with_parameters(mem.get(1..).unwrap_or(&[])))
+    }
+}
+
+/// The part of an instruction determined by its opcode word alone, which is
+/// what `Machine` caches per address
+#[derive(Copy, Clone)]
+struct Opcode {
+    code: u8,
+    modes: [ParameterMode; 3],
+}
 
-        let instruction_code = mem[0] % 100;
-        let parameter_modes = (mem[0] / 100)
-            .digits_reversed()
-            .map(|d| match d {
+impl Opcode {
+    fn decode<W: Word>(opcode: &W, address: usize) -> Result<Opcode, MachineError<W>> {
+        let invalid_opcode = || MachineError::InvalidOpcode {
+            address,
+            opcode: opcode.clone(),
+        };
+        // every opcode a real program uses fits in a u32, and dividing one of
+        // those is far cheaper than dividing a wider word
+        let (instruction_code, modes) = match opcode.to_u32() {
+            Some(narrow) => (
+                narrow % 100,
+                Opcode::decode_modes((narrow / 100).digits_reversed()),
+            ),
+            None if *opcode < W::zero() => return Err(invalid_opcode()),
+            None => {
+                let digits = opcode.to_string().into_bytes();
+                let (modes, code) = digits.split_at(digits.len() - 2);
+                let code = (code[0] - b'0') as u32 * 10 + (code[1] - b'0') as u32;
+                let modes = modes.iter().rev().map(|d| d - b'0');
+                (code, Opcode::decode_modes(modes))
+            }
+        };
+        let modes = modes.map_err(|mode| MachineError::InvalidParameterMode {
+            address,
+            opcode: opcode.clone(),
+            mode,
+        })?;
+        match instruction_code {
+            1..=9 | 99 => Ok(Opcode {
+                code: instruction_code as u8,
+                modes,
+            }),
+            _ => Err(invalid_opcode()),
+        }
+    }
+
+    /// the modes of the first three parameters, or the first invalid mode
+    /// digit, from the mode digits least significant first
+    fn decode_modes(mode_digits: impl Iterator<Item = u8>) -> Result<[ParameterMode; 3], u8> {
+        let mut modes = [ParameterMode::Positional; 3];
+        for (i, d) in mode_digits.enumerate() {
+            let mode = match d {
                 0 => ParameterMode::Positional,
                 1 => ParameterMode::Immediate,
                 2 => ParameterMode::Relative,
-                _ => panic!("Not a valid parameter mode: {}", d),
-            })
-            .collect::<Vec<ParameterMode>>();
+                _ => return Err(d),
+            };
+            if i < modes.len() {
+                modes[i] = mode;
+            }
+        }
+        Ok(modes)
+    }
 
-        match instruction_code {
+    /// build the instruction from the words following the opcode
+    fn with_parameters<W: Word>(self, mem: &[W]) -> Instruction<W> {
+        use Instruction::*;
+        let modes = &self.modes;
+        match self.code {
             1 => {
-                let (p1, p2, p3) = get_parameters(&mem[1..], &parameter_modes, 3);
+                let (p1, p2, p3) = get_parameters(mem, modes, 3);
                 Add(p1.unwrap(), p2.unwrap(), p3.unwrap())
             }
             2 => {
-                let (p1, p2, p3) = get_parameters(&mem[1..], &parameter_modes, 3);
+                let (p1, p2, p3) = get_parameters(mem, modes, 3);
                 Mult(p1.unwrap(), p2.unwrap(), p3.unwrap())
             }
             3 => {
-                let (p1, _, _) = get_parameters(&mem[1..], &parameter_modes, 1);
+                let (p1, _, _) = get_parameters(mem, modes, 1);
                 Input(p1.unwrap())
             }
             4 => {
-                let (p1, _, _) = get_parameters(&mem[1..], &parameter_modes, 1);
+                let (p1, _, _) = get_parameters(mem, modes, 1);
                 Output(p1.unwrap())
             }
             5 => {
-                let (p1, p2, _) = get_parameters(&mem[1..], &parameter_modes, 2);
+                let (p1, p2, _) = get_parameters(mem, modes, 2);
                 JumpTrue(p1.unwrap(), p2.unwrap())
             }
             6 => {
-                let (p1, p2, _) = get_parameters(&mem[1..], &parameter_modes, 2);
+                let (p1, p2, _) = get_parameters(mem, modes, 2);
                 JumpFalse(p1.unwrap(), p2.unwrap())
             }
             7 => {
-                let (p1, p2, p3) = get_parameters(&mem[1..], &parameter_modes, 3);
+                let (p1, p2, p3) = get_parameters(mem, modes, 3);
                 LessThan(p1.unwrap(), p2.unwrap(), p3.unwrap())
             }
             8 => {
-                let (p1, p2, p3) = get_parameters(&mem[1..], &parameter_modes, 3);
+                let (p1, p2, p3) = get_parameters(mem, modes, 3);
                 Equal(p1.unwrap(), p2.unwrap(), p3.unwrap())
             }
             9 => {
-                let (p1, _, _) = get_parameters(&mem[1..], &parameter_modes, 1);
+                let (p1, _, _) = get_parameters(mem, modes, 1);
                 AdjustRelativeBase(p1.unwrap())
             }
-            99 => Halt,
-            _ => panic!("Invalid opcode: {}", instruction_code),
+            _ => Halt,
+        }
+    }
+}
+
+/// Everything that can go wrong while loading or running a `Machine`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineError<W = i128> {
+    /// the program source contained a token that is not an integer
+    Parse { position: usize, token: String },
+    /// the word at `address` is not a known instruction
+    InvalidOpcode { address: usize, opcode: W },
+    /// the instruction at `address` has a parameter mode digit other than 0, 1 or 2
+    InvalidParameterMode { address: usize, opcode: W, mode: u8 },
+    /// the instruction at `address` tried to write to an immediate mode parameter
+    ImmediateDestination { address: usize, opcode: W },
+    /// the instruction at `address` needed input, but all input had been consumed
+    InputExhausted { address: usize, opcode: W },
+    /// the instruction at `address` referred to the negative memory location `target`
+    InvalidAddress {
+        address: usize,
+        opcode: W,
+        target: W,
+    },
+    /// the instruction at `address` overflowed under `Arithmetic::Checked`,
+    /// or computed an address too large for the machine's word type
+    Overflow { address: usize, opcode: W },
+    /// the instruction at `address` tried to write to the protected address `target`
+    ProtectedWrite {
+        address: usize,
+        opcode: W,
+        target: usize,
+    },
+}
+
+impl<W: Word> MachineError<W> {
+    /// the instruction pointer at the time of the error, if the machine was running
+    pub fn address(&self) -> Option<usize> {
+        use MachineError::*;
+        match self {
+            Parse { .. } => None,
+            InvalidOpcode { address, .. }
+            | InvalidParameterMode { address, .. }
+            | ImmediateDestination { address, .. }
+            | InputExhausted { address, .. }
+            | InvalidAddress { address, .. }
+            | Overflow { address, .. }
+            | ProtectedWrite { address, .. } => Some(*address),
+        }
+    }
+
+    /// the raw opcode word being executed at the time of the error, if any
+    pub fn opcode(&self) -> Option<W> {
+        use MachineError::*;
+        match self {
+            Parse { .. } => None,
+            InvalidOpcode { opcode, .. }
+            | InvalidParameterMode { opcode, .. }
+            | ImmediateDestination { opcode, .. }
+            | InputExhausted { opcode, .. }
+            | InvalidAddress { opcode, .. }
+            | Overflow { opcode, .. }
+            | ProtectedWrite { opcode, .. } => Some(opcode.clone()),
+        }
+    }
+}
+
+impl<W: Word> fmt::Display for MachineError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MachineError::*;
+        match self {
+            Parse { position, token } => {
+                write!(
+                    f,
+                    "failed to parse word {} of the program: {:?}",
+                    position, token
+                )
+            }
+            InvalidOpcode { address, opcode } => {
+                write!(f, "invalid opcode {} at address {}", opcode, address)
+            }
+            InvalidParameterMode {
+                address,
+                opcode,
+                mode,
+            } => write!(
+                f,
+                "invalid parameter mode {} in opcode {} at address {}",
+                mode, opcode, address
+            ),
+            ImmediateDestination { address, opcode } => write!(
+                f,
+                "cannot use immediate mode as a destination (opcode {} at address {})",
+                opcode, address
+            ),
+            InputExhausted { address, opcode } => write!(
+                f,
+                "input exhausted (opcode {} at address {})",
+                opcode, address
+            ),
+            InvalidAddress {
+                address,
+                opcode,
+                target,
+            } => write!(
+                f,
+                "invalid memory address {} (opcode {} at address {})",
+                target, opcode, address
+            ),
+            Overflow { address, opcode } => write!(
+                f,
+                "arithmetic overflow (opcode {} at address {})",
+                opcode, address
+            ),
+            ProtectedWrite {
+                address,
+                opcode,
+                target,
+            } => write!(
+                f,
+                "write to protected address {} (opcode {} at address {})",
+                target, opcode, address
+            ),
         }
     }
 }
 
+impl<W: Word> Error for MachineError<W> {}
+
+/// An Intcode machine, storing its memory in `M`. The default dense `Vec`
+/// is fastest for well-behaved programs, while `PagedMemory` keeps programs
+/// that write to huge addresses from allocating everything below them.
 #[derive(Clone)]
-pub struct Machine {
-    pub memory: Vec<i128>,
+pub struct Machine<M: Memory = Vec<i128>> {
+    pub memory: M,
     mem_ptr: usize,
-    pub input: Vec<i128>,
+    pub input: Vec<M::Word>,
     input_ptr: usize,
-    pub output: Vec<i128>,
+    pub output: Vec<M::Word>,
     await_empty_input: bool,
+    arithmetic: Arithmetic,
     relative_base: isize,
+    /// instructions left before `run` stops with `Status::BudgetExhausted`
+    instruction_budget: Option<u64>,
+    deadline: Option<Instant>,
+    /// instructions until the clock is next checked against `deadline`
+    until_clock_check: u32,
+    /// the undo log for `step_back`, if one is being kept
+    history: Option<History<M::Word>>,
+    hooks: Hooks<M::Word>,
+    self_modification: Option<SelfModificationWatch<M::Word>>,
+    protection: Protection,
+    /// the decoded opcode last seen at each address, along with the word it
+    /// was decoded from
+    decoded: Vec<Option<(u32, Opcode)>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status<W = i128> {
+    Waiting,
+    Halted,
+    /// the program output a value; only returned by `run_to_output`
+    Output(W),
+    /// the instruction budget or deadline ran out. The machine stopped
+    /// before the next instruction and can be resumed once it's extended.
+    BudgetExhausted,
+}
+
+/// The result of `Machine::next_packet`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet<const N: usize, W = i128> {
+    Complete([W; N]),
+    /// the machine halted or waited for input after producing only these
+    /// values of the packet (usually none)
+    Stopped(Status<W>, Vec<W>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRead<W = i128> {
+    pub address: usize,
+    pub value: W,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryWrite<W = i128> {
+    pub address: usize,
+    pub old: W,
+    pub new: W,
+}
+
+/// The effects of executing a single instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Step<W = i128> {
+    pub address: usize,
+    pub opcode: W,
+    pub instruction: Instruction<W>,
+    operands: [Option<W>; 3],
+    reads: [Option<MemoryRead<W>>; 2],
+    pub write: Option<MemoryWrite<W>>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+    /// the relative base before and after an `AdjustRelativeBase`
+    pub relative_base: Option<(isize, isize)>,
+    /// whether a jump instruction's condition held
+    pub jumped: bool,
+}
+
+impl<W: Word> Step<W> {
+    fn new(address: usize, opcode: W, instruction: Instruction<W>) -> Self {
+        Step {
+            address,
+            opcode,
+            instruction,
+            operands: [None, None, None],
+            reads: [None, None],
+            write: None,
+            input: None,
+            output: None,
+            relative_base: None,
+            jumped: false,
+        }
+    }
+
+    /// the resolved value of each of the instruction's parameters: the value
+    /// read for inputs, and the address written to for destinations. A jump
+    /// target is only resolved if the jump is taken.
+    pub fn operands(&self) -> impl Iterator<Item = Option<W>> + '_ {
+        self.operands[..self.instruction.size() - 1].iter().cloned()
+    }
+
+    /// the memory cells read to resolve the instruction's parameters
+    pub fn reads(&self) -> impl Iterator<Item = &MemoryRead<W>> {
+        self.reads.iter().flatten()
+    }
+}
+
+/// Collects the effects of an instruction as it executes. `run` uses `()`, so
+/// that none of this bookkeeping is paid for unless someone is stepping.
+trait Recorder<W> {
+    fn operand(&mut self, _value: &W) {}
+    fn read(&mut self, _read: MemoryRead<W>) {}
+    fn write(&mut self, _write: MemoryWrite<W>) {}
+    fn input(&mut self, _value: &W) {}
+    fn output(&mut self, _value: &W) {}
+    fn relative_base(&mut self, _old: isize, _new: isize) {}
+    fn jumped(&mut self) {}
+}
+
+impl<W> Recorder<W> for () {}
+
+impl<W: Word> Recorder<W> for Step<W> {
+    fn operand(&mut self, value: &W) {
+        if let Some(slot) = self.operands.iter_mut().find(|o| o.is_none()) {
+            *slot = Some(value.clone());
+        }
+    }
+
+    fn read(&mut self, read: MemoryRead<W>) {
+        if let Some(slot) = self.reads.iter_mut().find(|r| r.is_none()) {
+            *slot = Some(read);
+        }
+    }
+
+    fn write(&mut self, write: MemoryWrite<W>) {
+        self.write = Some(write);
+    }
+
+    fn input(&mut self, value: &W) {
+        self.input = Some(value.clone());
+    }
+
+    fn output(&mut self, value: &W) {
+        self.output = Some(value.clone());
+    }
+
+    fn relative_base(&mut self, old: isize, new: isize) {
+        self.relative_base = Some((old, new));
+    }
+
+    fn jumped(&mut self) {
+        self.jumped = true;
+    }
+}
+
+/// where in the program an instruction is being executed, for error reporting
+struct Location<W> {
+    address: usize,
+    opcode: W,
+}
+
+impl<W: Word> Location<W> {
+    fn error_overflow(&self) -> MachineError<W> {
+        MachineError::Overflow {
+            address: self.address,
+            opcode: self.opcode.clone(),
+        }
+    }
+
+    fn error_immediate_destination(&self) -> MachineError<W> {
+        MachineError::ImmediateDestination {
+            address: self.address,
+            opcode: self.opcode.clone(),
+        }
+    }
+
+    fn check_address(&self, target: W) -> Result<usize, MachineError<W>> {
+        target
+            .to_address()
+            .ok_or_else(|| MachineError::InvalidAddress {
+                address: self.address,
+                opcode: self.opcode.clone(),
+                target,
+            })
+    }
 }
 
-pub enum Status {
+// `Step` is returned by value on every instruction, so boxing it would cost
+// an allocation per step
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StepOutcome<W = i128> {
+    Executed(Step<W>),
+    /// the next instruction needs input that hasn't arrived yet
     Waiting,
+    /// the next instruction is a halt
     Halted,
 }
 
+/// the cache grows to cover the highest address executed, so it stops here
+/// to keep a jump to a huge address from allocating a huge cache
+const DECODE_CACHE_LIMIT: usize = 1 << 20;
+
+/// reading the clock costs far more than an instruction, so a deadline is
+/// only checked this often
+const CLOCK_CHECK_INTERVAL: u32 = 1024;
+
 impl Machine {
     pub fn new(src: &str, input: Vec<i128>) -> Machine {
-        let memory = src
+        match Machine::try_new(src, input) {
+            Ok(machine) => machine,
+            Err(e) => panic!("Failed to parse! {}", e),
+        }
+    }
+
+    pub fn try_new(src: &str, input: Vec<i128>) -> Result<Machine, MachineError> {
+        Machine::try_with_memory(src, input)
+    }
+
+    /// produce an annotated listing of the machine's current memory
+    pub fn disassemble(&self) -> disassembler::Listing {
+        disassembler::disassemble(&self.memory)
+    }
+}
+
+impl<W: Word, M: Memory<Word = W>> Machine<M> {
+    /// like `new`, but with the memory backend chosen by the caller, e.g.
+    /// `let machine: Machine<PagedMemory> = Machine::with_memory(src, vec![]);`
+    pub fn with_memory(src: &str, input: Vec<W>) -> Self {
+        match Machine::try_with_memory(src, input) {
+            Ok(machine) => machine,
+            Err(e) => panic!("Failed to parse! {}", e),
+        }
+    }
+
+    pub fn try_with_memory(src: &str, input: Vec<W>) -> Result<Self, MachineError<W>> {
+        let words = src
             .split(',')
-            .map(|code| code.trim().parse::<i128>())
-            .collect::<Result<Vec<_>, _>>();
-        if let Ok(memory) = memory {
-            Machine {
-                memory,
-                input,
-                input_ptr: 0,
-                mem_ptr: 0,
-                output: vec![],
-                await_empty_input: false,
-                relative_base: 0,
-            }
-        } else {
-            panic!("Failed to parse! {:?}", memory);
+            .enumerate()
+            .map(|(position, code)| {
+                code.trim().parse::<W>().map_err(|_| MachineError::Parse {
+                    position,
+                    token: code.trim().to_owned(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Machine::from_words(words, input))
+    }
+
+    /// a machine with `words` already parsed as its memory
+    fn from_words(words: Vec<W>, input: Vec<W>) -> Self {
+        Machine {
+            memory: M::from_words(words),
+            input,
+            input_ptr: 0,
+            mem_ptr: 0,
+            output: vec![],
+            await_empty_input: false,
+            arithmetic: Arithmetic::default(),
+            relative_base: 0,
+            instruction_budget: None,
+            deadline: None,
+            until_clock_check: 0,
+            history: None,
+            hooks: Hooks::default(),
+            self_modification: None,
+            protection: Protection::default(),
+            decoded: vec![],
         }
     }
 
@@ -182,115 +718,715 @@ impl Machine {
         self.await_empty_input = true;
     }
 
-    /// ensure that the machine has memory to at least `destination`
-    fn ensure_memory(&mut self, destination: usize) {
-        if destination >= self.memory.len() {
-            let target_size = max(2 * self.memory.len(), destination);
-            self.memory
-                .append(&mut vec![0; target_size - self.memory.len() + 1]);
+    /// choose how `Add`, `Mult` and `AdjustRelativeBase` handle overflow;
+    /// the default is `Arithmetic::Checked`
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    /// limit `run`, `run_with`, `run_to_output` and `next_packet` to
+    /// `instructions` more instructions in total, or lift the limit with
+    /// `None`. Once it's spent they return `Status::BudgetExhausted`. Single
+    /// steps aren't counted.
+    pub fn set_instruction_budget(&mut self, instructions: Option<u64>) {
+        self.instruction_budget = instructions;
+    }
+
+    /// the instructions left in the budget, if there is one
+    pub fn instruction_budget(&self) -> Option<u64> {
+        self.instruction_budget
+    }
+
+    /// stop running with `Status::BudgetExhausted` once `deadline` has
+    /// passed, or never with `None`. The clock is checked every
+    /// `CLOCK_CHECK_INTERVAL` instructions, so the machine may overrun a
+    /// little.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.until_clock_check = 0;
+    }
+
+    /// `set_deadline` for `timeout` from now
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.set_deadline(Some(Instant::now() + timeout));
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// whether the budget or deadline stops the next instruction from running
+    fn out_of_budget(&mut self) -> bool {
+        if self.instruction_budget == Some(0) {
+            return true;
+        }
+        match self.deadline {
+            Some(deadline) if self.until_clock_check == 0 => {
+                self.until_clock_check = CLOCK_CHECK_INTERVAL;
+                Instant::now() >= deadline
+            }
+            Some(_) => {
+                self.until_clock_check -= 1;
+                false
+            }
+            None => false,
         }
     }
 
-    fn set_memory(&mut self, destination: usize, value: i128) {
-        self.ensure_memory(destination);
-        self.memory[destination] = value;
+    fn spend_budget(&mut self) {
+        if let Some(remaining) = &mut self.instruction_budget {
+            *remaining -= 1;
+        }
     }
 
-    fn get_memory(&mut self, destination: usize) -> i128 {
-        self.ensure_memory(destination);
-        self.memory[destination]
+    pub fn run(&mut self) -> Status<W> {
+        match self.try_run() {
+            Ok(status) => status,
+            Err(e) => panic!("{}", e),
+        }
     }
 
-    pub fn run(&mut self) -> Status {
-        use Instruction::*;
+    pub fn try_run(&mut self) -> Result<Status<W>, MachineError<W>> {
+        self.with_buffers(|machine, input, output| {
+            machine.run_recording(input, output, Recording::BUFFERED)
+        })
+    }
+
+    /// run until the program halts or waits for input, reading input from
+    /// `input` and sending output to `output` instead of the machine's own
+    /// `input` and `output` buffers. When `input` runs dry the machine waits
+    /// if `wait_on_input` was called, and fails otherwise.
+    pub fn run_with<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Status<W>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+    {
+        self.run_recording(input, output, Recording::EXTERNAL)
+    }
+
+    fn run_recording<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        recording: Recording,
+    ) -> Result<Status<W>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+    {
         loop {
-            let instruction = Instruction::decode(&self.memory[self.mem_ptr..]);
-            let mut should_increment_ptr = true;
-            match &instruction {
-                Halt => return Status::Halted,
-                Add(a, b, dest) => {
-                    let sum = self.resolve(a) + self.resolve(b);
-                    let mem_dest = self.resolve_as_destination(dest);
-                    self.set_memory(mem_dest, sum);
+            if self.out_of_budget() {
+                return Ok(Status::BudgetExhausted);
+            }
+            if let Some(status) = self.execute_next(input, output, recording)? {
+                return Ok(status);
+            }
+            self.spend_budget();
+        }
+    }
+
+    pub fn run_to_output(&mut self) -> Status<W> {
+        match self.try_run_to_output() {
+            Ok(status) => status,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// run until the program outputs a value, halts or waits for input. The
+    /// value is returned as `Status::Output` rather than added to `output`.
+    pub fn try_run_to_output(&mut self) -> Result<Status<W>, MachineError<W>> {
+        self.with_buffers(|machine, input, _| {
+            let mut value = None;
+            loop {
+                if machine.out_of_budget() {
+                    return Ok(Status::BudgetExhausted);
                 }
-                Mult(a, b, dest) => {
-                    let prod = self.resolve(a) * self.resolve(b);
-                    let mem_dest = self.resolve_as_destination(dest);
-                    self.set_memory(mem_dest, prod);
+                let mut sink = |v| value = Some(v);
+                // the output is returned rather than kept in the buffer
+                let recording = Recording {
+                    input_kept: true,
+                    output_kept: false,
+                };
+                if let Some(status) = machine.execute_next(input, &mut sink, recording)? {
+                    return Ok(status);
                 }
-                Input(dest) => {
-                    if self.await_empty_input && self.input_ptr == self.input.len() {
-                        return Status::Waiting;
-                    }
-                    let value = self.input[self.input_ptr];
-                    self.input_ptr += 1;
-                    let mem_dest = self.resolve_as_destination(dest);
-                    self.set_memory(mem_dest, value);
+                machine.spend_budget();
+                if let Some(value) = value {
+                    return Ok(Status::Output(value));
                 }
-                Output(dest) => {
-                    let value = self.resolve(dest);
-                    self.output.push(value);
+            }
+        })
+    }
+
+    pub fn next_packet<const N: usize>(&mut self) -> Packet<N, W> {
+        match self.try_next_packet() {
+            Ok(packet) => packet,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// run until the program has output `N` more values, e.g. the
+    /// (colour, turn) pairs of the day 11 robot or the (x, y, tile) triples
+    /// of the day 13 arcade
+    pub fn try_next_packet<const N: usize>(&mut self) -> Result<Packet<N, W>, MachineError<W>> {
+        let mut packet = Vec::with_capacity(N);
+        while packet.len() < N {
+            match self.try_run_to_output()? {
+                Status::Output(value) => packet.push(value),
+                status => return Ok(Packet::Stopped(status, packet)),
+            }
+        }
+        match packet.try_into() {
+            Ok(packet) => Ok(Packet::Complete(packet)),
+            Err(_) => unreachable!("the packet has exactly N values"),
+        }
+    }
+
+    /// call `f` with the machine's own input and output buffers as I/O
+    fn with_buffers<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self, &mut BufferedInput<W>, &mut Vec<W>) -> T,
+    ) -> T {
+        let mut input = BufferedInput {
+            values: mem::take(&mut self.input),
+            ptr: self.input_ptr,
+        };
+        let mut output = mem::take(&mut self.output);
+        let result = f(self, &mut input, &mut output);
+        self.input = input.values;
+        self.input_ptr = input.ptr;
+        self.output = output;
+        result
+    }
+
+    pub fn mem_ptr(&self) -> usize {
+        self.mem_ptr
+    }
+
+    /// how many values of `input` have been read so far
+    pub fn input_ptr(&self) -> usize {
+        self.input_ptr
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    /// decode the instruction that the next `step` will execute
+    pub fn current_instruction(&self) -> Result<Instruction<W>, MachineError<W>> {
+        let mut words = [W::zero(), W::zero(), W::zero(), W::zero()];
+        self.memory.read_into(self.mem_ptr, &mut words);
+        Instruction::decode(&words, self.mem_ptr)
+    }
+
+    /// execute a single instruction
+    pub fn step(&mut self) -> Result<StepOutcome<W>, MachineError<W>> {
+        self.with_buffers(|machine, input, output| {
+            machine.step_recording(input, output, Recording::BUFFERED)
+        })
+    }
+
+    /// execute a single instruction, with I/O as for `run_with`
+    pub fn step_with<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<StepOutcome<W>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+    {
+        self.step_recording(input, output, Recording::EXTERNAL)
+    }
+
+    fn step_recording<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        recording: Recording,
+    ) -> Result<StepOutcome<W>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+    {
+        let (opcode, instruction) = self.fetch(self.mem_ptr)?;
+        let mut step = Step::new(self.mem_ptr, opcode.clone(), instruction.clone());
+        Ok(
+            match self.execute(opcode, instruction, input, output, &mut step)? {
+                None => {
+                    self.remember(&step, recording);
+                    StepOutcome::Executed(step)
                 }
-                JumpTrue(check, dest) => {
-                    if self.resolve(check) != 0 {
-                        should_increment_ptr = false;
-                        self.mem_ptr = self.resolve(dest) as usize;
-                    }
+                Some(Status::Waiting) => StepOutcome::Waiting,
+                Some(Status::Halted) => StepOutcome::Halted,
+                Some(Status::Output(_)) | Some(Status::BudgetExhausted) => {
+                    unreachable!("execute only stops to wait or halt")
                 }
-                JumpFalse(check, dest) => {
-                    if self.resolve(check) == 0 {
-                        should_increment_ptr = false;
-                        self.mem_ptr = self.resolve(dest) as usize;
+            },
+        )
+    }
+
+    /// fetch and execute the instruction at `mem_ptr`, adding it to the
+    /// history if one is being kept
+    fn execute_next<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        recording: Recording,
+    ) -> Result<Option<Status<W>>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+    {
+        let (opcode, instruction) = self.fetch(self.mem_ptr)?;
+        if self.history.is_none() {
+            return self.execute(opcode, instruction, input, output, &mut ());
+        }
+        let mut step = Step::new(self.mem_ptr, opcode.clone(), instruction.clone());
+        let status = self.execute(opcode, instruction, input, output, &mut step)?;
+        if status.is_none() {
+            self.remember(&step, recording);
+        }
+        Ok(status)
+    }
+
+    /// execute `instruction`, the instruction at `mem_ptr`. Returns the status
+    /// if the machine can't make progress, in which case nothing has changed.
+    fn execute<I, O, R>(
+        &mut self,
+        opcode: W,
+        instruction: Instruction<W>,
+        input: &mut I,
+        output: &mut O,
+        recorder: &mut R,
+    ) -> Result<Option<Status<W>>, MachineError<W>>
+    where
+        I: InputSource<W> + ?Sized,
+        O: OutputSink<W> + ?Sized,
+        R: Recorder<W>,
+    {
+        use Instruction::*;
+        let address = self.mem_ptr;
+        let at = &Location { address, opcode };
+        let mut next_ptr = address + instruction.size();
+        // noted before anything is written, so that an instruction
+        // overwriting its own opcode counts as self-modification
+        self.note_execution(address);
+        match &instruction {
+            Halt => return Ok(Some(Status::Halted)),
+            Add(a, b, dest) => {
+                let sum = self
+                    .resolve(a, at, recorder)?
+                    .add(&self.resolve(b, at, recorder)?, self.arithmetic)
+                    .ok_or_else(|| at.error_overflow())?;
+                self.write(dest, sum, at, recorder)?;
+            }
+            Mult(a, b, dest) => {
+                let prod = self
+                    .resolve(a, at, recorder)?
+                    .mul(&self.resolve(b, at, recorder)?, self.arithmetic)
+                    .ok_or_else(|| at.error_overflow())?;
+                self.write(dest, prod, at, recorder)?;
+            }
+            Input(dest) => {
+                // resolve the destination first, so that a bad one doesn't
+                // swallow a value
+                let destination = self.resolve_as_writable(dest, at)?;
+                let value = match input.next_input() {
+                    Some(value) => value,
+                    None if self.await_empty_input => return Ok(Some(Status::Waiting)),
+                    None => {
+                        return Err(MachineError::InputExhausted {
+                            address,
+                            opcode: at.opcode.clone(),
+                        })
                     }
+                };
+                recorder.input(&value);
+                self.store(destination, value, recorder);
+            }
+            Output(dest) => {
+                let value = self.resolve(dest, at, recorder)?;
+                recorder.output(&value);
+                output.send_output(value);
+            }
+            JumpTrue(check, dest) => {
+                if !self.resolve(check, at, recorder)?.is_zero() {
+                    recorder.jumped();
+                    next_ptr = self.resolve_as_jump_target(dest, at, recorder)?;
+                }
+            }
+            JumpFalse(check, dest) => {
+                if self.resolve(check, at, recorder)?.is_zero() {
+                    recorder.jumped();
+                    next_ptr = self.resolve_as_jump_target(dest, at, recorder)?;
                 }
-                LessThan(a, b, dest) => {
-                    let write_value = if self.resolve(a) < self.resolve(b) {
-                        1
+            }
+            LessThan(a, b, dest) => {
+                let write_value =
+                    if self.resolve(a, at, recorder)? < self.resolve(b, at, recorder)? {
+                        W::one()
                     } else {
-                        0
+                        W::zero()
                     };
-                    let mem_dest = self.resolve_as_destination(dest);
-                    self.set_memory(mem_dest, write_value);
-                }
-                Equal(a, b, dest) => {
-                    let write_value = if self.resolve(a) == self.resolve(b) {
-                        1
+                self.write(dest, write_value, at, recorder)?;
+            }
+            Equal(a, b, dest) => {
+                let write_value =
+                    if self.resolve(a, at, recorder)? == self.resolve(b, at, recorder)? {
+                        W::one()
                     } else {
-                        0
+                        W::zero()
                     };
-                    let mem_dest = self.resolve_as_destination(dest);
-                    self.set_memory(mem_dest, write_value);
-                }
-                AdjustRelativeBase(a) => {
-                    let adjust_val = self.resolve(a);
-                    self.relative_base += adjust_val as isize
+                self.write(dest, write_value, at, recorder)?;
+            }
+            AdjustRelativeBase(a) => {
+                let adjust_val = self.resolve(a, at, recorder)?;
+                let old = self.relative_base;
+                // the relative base has to stay within an isize, so it can
+                // overflow even when the word type doesn't
+                self.relative_base = W::from_isize(old)
+                    .add(&adjust_val, self.arithmetic)
+                    .and_then(|new| new.to_isize_with(self.arithmetic))
+                    .ok_or_else(|| at.error_overflow())?;
+                recorder.relative_base(old, self.relative_base);
+            }
+        }
+
+        self.mem_ptr = next_ptr;
+        Ok(None)
+    }
+
+    /// decode the instruction at `address`, reusing the last decoding of that
+    /// address's opcode if the word there hasn't changed since. Parameters are
+    /// always read from memory, so self-modifying code and writes through the
+    /// public `memory` are both picked up.
+    fn fetch(&mut self, address: usize) -> Result<(W, Instruction<W>), MachineError<W>> {
+        let mut words = [W::zero(), W::zero(), W::zero(), W::zero()];
+        self.memory.read_into(address, &mut words);
+        if self.hooks.maps_reads(address, words.len()) {
+            return self.fetch_hooked(address, words);
+        }
+        let narrow = words[0].to_u32();
+        let opcode = match (self.decoded.get(address), narrow) {
+            (Some(&Some((cached_word, opcode))), Some(word)) if cached_word == word => opcode,
+            _ => {
+                let opcode = Opcode::decode(&words[0], address)?;
+                // opcodes too wide for the cache, or at addresses past its
+                // limit, still work, they're just decoded every time
+                if let (Some(word), true) = (narrow, address < DECODE_CACHE_LIMIT) {
+                    if address >= self.decoded.len() {
+                        self.decoded.resize(address + 1, None);
+                    }
+                    self.decoded[address] = Some((word, opcode));
                 }
+                opcode
             }
+        };
+        let [word, parameters @ ..] = words;
+        Ok((word, opcode.with_parameters(&parameters)))
+    }
 
-            if should_increment_ptr {
-                self.mem_ptr += instruction.size();
+    /// `fetch` for an instruction with words mapped to read hooks, which are
+    /// only called for the words the instruction actually has. The decode
+    /// cache is bypassed since the opcode can change on every read.
+    fn fetch_hooked(
+        &mut self,
+        address: usize,
+        mut words: [W; 4],
+    ) -> Result<(W, Instruction<W>), MachineError<W>> {
+        if let Some(word) = self.hooks.read(address) {
+            words[0] = word;
+        }
+        let opcode = Opcode::decode(&words[0], address)?;
+        let size = opcode.with_parameters(&words[1..]).size();
+        for (i, word) in words.iter_mut().enumerate().take(size).skip(1) {
+            if let Some(value) = self.hooks.read(address + i) {
+                *word = value;
             }
         }
+        let [word, parameters @ ..] = words;
+        Ok((word, opcode.with_parameters(&parameters)))
     }
 
-    pub fn add_input(&mut self, new_input: i128) {
+    pub fn add_input(&mut self, new_input: W) {
         self.input.push(new_input)
     }
 
-    fn resolve(&mut self, parameter: &Parameter) -> i128 {
-        match parameter.mode {
-            ParameterMode::Immediate => parameter.value,
-            _ => self.get_memory(self.resolve_as_destination(parameter)),
+    fn resolve<R: Recorder<W>>(
+        &mut self,
+        parameter: &Parameter<W>,
+        at: &Location<W>,
+        recorder: &mut R,
+    ) -> Result<W, MachineError<W>> {
+        let value = match parameter.mode {
+            ParameterMode::Immediate => parameter.value.clone(),
+            _ => {
+                let source = self.resolve_as_destination(parameter, at)?;
+                let value = match self.hooks.read(source) {
+                    Some(value) => value,
+                    None => self.memory.read(source),
+                };
+                recorder.read(MemoryRead {
+                    address: source,
+                    value: value.clone(),
+                });
+                value
+            }
+        };
+        recorder.operand(&value);
+        Ok(value)
+    }
+
+    fn write<R: Recorder<W>>(
+        &mut self,
+        parameter: &Parameter<W>,
+        value: W,
+        at: &Location<W>,
+        recorder: &mut R,
+    ) -> Result<(), MachineError<W>> {
+        let destination = self.resolve_as_writable(parameter, at)?;
+        self.store(destination, value, recorder);
+        Ok(())
+    }
+
+    fn store<R: Recorder<W>>(&mut self, destination: usize, value: W, recorder: &mut R) {
+        recorder.operand(&W::from_usize(destination));
+        let old = self.memory.read(destination);
+        self.note_write(destination, &old, &value);
+        recorder.write(MemoryWrite {
+            address: destination,
+            old,
+            new: value.clone(),
+        });
+        self.hooks.write(destination, &value);
+        self.memory.write(destination, value);
+    }
+
+    fn resolve_as_destination(
+        &self,
+        parameter: &Parameter<W>,
+        at: &Location<W>,
+    ) -> Result<usize, MachineError<W>> {
+        let target = match parameter.mode {
+            ParameterMode::Immediate => return Err(at.error_immediate_destination()),
+            ParameterMode::Positional => parameter.value.clone(),
+            // an address that overflows is never valid, whatever the arithmetic
+            ParameterMode::Relative => W::from_isize(self.relative_base)
+                .add(&parameter.value, Arithmetic::Checked)
+                .ok_or_else(|| at.error_overflow())?,
+        };
+        at.check_address(target)
+    }
+
+    /// `resolve_as_destination` for a parameter about to be written to
+    fn resolve_as_writable(
+        &self,
+        parameter: &Parameter<W>,
+        at: &Location<W>,
+    ) -> Result<usize, MachineError<W>> {
+        let destination = self.resolve_as_destination(parameter, at)?;
+        if self.protection.covers(destination) {
+            return Err(MachineError::ProtectedWrite {
+                address: at.address,
+                opcode: at.opcode.clone(),
+                target: destination,
+            });
         }
+        Ok(destination)
+    }
+
+    fn resolve_as_jump_target<R: Recorder<W>>(
+        &mut self,
+        parameter: &Parameter<W>,
+        at: &Location<W>,
+        recorder: &mut R,
+    ) -> Result<usize, MachineError<W>> {
+        let target = self.resolve(parameter, at, recorder)?;
+        at.check_address(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_error() {
+        let result = Machine::try_new("1,0,x,0,99", vec![]);
+        assert!(
+            result.err()
+                == Some(MachineError::Parse {
+                    position: 2,
+                    token: "x".to_owned()
+                })
+        );
     }
 
-    fn resolve_as_destination(&self, parameter: &Parameter) -> usize {
-        match parameter.mode {
-            ParameterMode::Immediate => panic!("Cannot use immediate mode as a destination!"),
-            ParameterMode::Positional => parameter.value as usize,
-            ParameterMode::Relative => {
-                (self.relative_base as i128 + parameter.value).abs() as usize
+    #[test]
+    fn test_invalid_opcode() {
+        let mut machine = Machine::new("1,0,0,0,42", vec![]);
+        assert!(
+            machine.try_run().err()
+                == Some(MachineError::InvalidOpcode {
+                    address: 4,
+                    opcode: 42
+                })
+        );
+    }
+
+    #[test]
+    fn test_invalid_parameter_mode() {
+        let mut machine = Machine::new("301,0,0,0,99", vec![]);
+        let err = machine.try_run().err().unwrap();
+        assert!(err.address() == Some(0));
+        assert!(err.opcode() == Some(301));
+        assert!(
+            err == MachineError::InvalidParameterMode {
+                address: 0,
+                opcode: 301,
+                mode: 3
             }
+        );
+    }
+
+    #[test]
+    fn test_immediate_destination() {
+        let mut machine = Machine::new("11101,1,1,0,99", vec![]);
+        assert!(
+            machine.try_run().err()
+                == Some(MachineError::ImmediateDestination {
+                    address: 0,
+                    opcode: 11101
+                })
+        );
+    }
+
+    #[test]
+    fn test_input_exhausted() {
+        let mut machine = Machine::new("3,0,3,0,99", vec![7]);
+        assert!(
+            machine.try_run().err()
+                == Some(MachineError::InputExhausted {
+                    address: 2,
+                    opcode: 3
+                })
+        );
+    }
+
+    #[test]
+    fn test_negative_address() {
+        let mut machine = Machine::new("109,-5,204,0,99", vec![]);
+        assert!(
+            machine.try_run().err()
+                == Some(MachineError::InvalidAddress {
+                    address: 2,
+                    opcode: 204,
+                    target: -5
+                })
+        );
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // OUT #8, then rewrite the opcode at 0 to a positional OUT and jump
+        // back to it, so the cached decoding of address 0 must be dropped
+        let mut machine = Machine::new("104,8,1101,4,0,0,1105,1,0", vec![]);
+        for _ in 0..4 {
+            machine.step().unwrap();
         }
+        assert!(machine.output == vec![8, 0]);
+    }
+
+    #[test]
+    fn test_external_code_change() {
+        let mut machine = Machine::new("3,7,4,7,1105,1,0,0", vec![5]);
+        machine.wait_on_input();
+        assert!(machine.run() == Status::Waiting);
+        // switch the OUT at 2 to immediate mode between runs
+        machine.memory[2] = 104;
+        machine.add_input(6);
+        assert!(machine.run() == Status::Waiting);
+        assert!(machine.output == vec![5, 7]);
+    }
+
+    #[test]
+    fn test_run_to_output() {
+        let mut machine = Machine::new("104,1,104,2,3,0,104,3,99", vec![]);
+        machine.wait_on_input();
+        assert!(machine.run_to_output() == Status::Output(1));
+        assert!(machine.run_to_output() == Status::Output(2));
+        assert!(machine.run_to_output() == Status::Waiting);
+        machine.add_input(0);
+        assert!(machine.run_to_output() == Status::Output(3));
+        assert!(machine.run_to_output() == Status::Halted);
+        assert!(machine.output.is_empty());
+    }
+
+    #[test]
+    fn test_next_packet() {
+        let mut machine = Machine::new("104,1,104,2,104,3,3,0,104,4,99", vec![]);
+        machine.wait_on_input();
+        assert!(machine.next_packet() == Packet::Complete([1, 2]));
+        assert!(machine.next_packet::<2>() == Packet::Stopped(Status::Waiting, vec![3]));
+        machine.add_input(0);
+        assert!(machine.next_packet() == Packet::Complete([4]));
+        assert!(machine.next_packet::<3>() == Packet::Stopped(Status::Halted, vec![]));
+    }
+
+    #[test]
+    fn test_instruction_budget() {
+        // outputs 1, 2, 3 then loops forever
+        let src = "104,1,104,2,104,3,1105,1,6";
+        let mut machine = Machine::new(src, vec![]);
+        machine.set_instruction_budget(Some(2));
+        assert!(machine.run() == Status::BudgetExhausted);
+        assert!(machine.output == vec![1, 2] && machine.mem_ptr() == 4);
+        assert!(machine.run() == Status::BudgetExhausted);
+
+        machine.set_instruction_budget(Some(100));
+        assert!(machine.run_to_output() == Status::Output(3));
+        assert!(machine.instruction_budget() == Some(99));
+        assert!(machine.run() == Status::BudgetExhausted);
+        assert!(machine.mem_ptr() == 6 && machine.instruction_budget() == Some(0));
+
+        // waiting doesn't use up the budget
+        let mut machine = Machine::new("3,0,99", vec![]);
+        machine.wait_on_input();
+        machine.set_instruction_budget(Some(1));
+        assert!(machine.run() == Status::Waiting);
+        machine.add_input(7);
+        assert!(machine.run() == Status::BudgetExhausted);
+        machine.set_instruction_budget(None);
+        assert!(machine.run() == Status::Halted);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut machine = Machine::new("1105,1,0", vec![]);
+        machine.set_timeout(Duration::from_millis(10));
+        assert!(machine.run() == Status::BudgetExhausted);
+        assert!(machine.deadline().unwrap() <= Instant::now());
+        assert!(machine.run() == Status::BudgetExhausted);
+        machine.set_deadline(None);
+        machine.set_instruction_budget(Some(10));
+        assert!(machine.run() == Status::BudgetExhausted);
+        assert!(machine.mem_ptr() == 0);
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        // the missing operands read as 0, so this adds memory[0] to itself
+        let mut machine = Machine::new("1,0", vec![]);
+        assert!(machine.try_run().is_err());
+        assert!(machine.memory[0] == 2);
     }
 }
@@ -0,0 +1,212 @@
+use super::{Machine, MachineError, Status};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug)]
+pub enum AsciiError {
+    Machine(MachineError),
+    Io(io::Error),
+    /// the text to send contained a character that isn't ASCII
+    NonAscii(char),
+}
+
+impl From<MachineError> for AsciiError {
+    fn from(e: MachineError) -> Self {
+        AsciiError::Machine(e)
+    }
+}
+
+impl From<io::Error> for AsciiError {
+    fn from(e: io::Error) -> Self {
+        AsciiError::Io(e)
+    }
+}
+
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsciiError::Machine(e) => write!(f, "{}", e),
+            AsciiError::Io(e) => write!(f, "ascii session failed: {}", e),
+            AsciiError::NonAscii(c) => write!(f, "can't send non-ASCII character {:?}", c),
+        }
+    }
+}
+
+impl Error for AsciiError {}
+
+/// Machine output split into text and the values that aren't ASCII
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsciiOutput {
+    pub text: String,
+    /// values outside `0..=127`, such as a final answer, in output order
+    pub values: Vec<i128>,
+}
+
+impl AsciiOutput {
+    pub fn decode(output: &[i128]) -> Self {
+        let mut decoded = AsciiOutput::default();
+        for &value in output {
+            match value {
+                0..=127 => decoded.text.push(value as u8 as char),
+                _ => decoded.values.push(value),
+            }
+        }
+        decoded
+    }
+}
+
+/// A `Machine` wrapper for programs that talk in ASCII.
+///
+/// The machine is set to wait on input, so it can be run until it asks for
+/// the next line.
+pub struct Ascii {
+    machine: Machine,
+}
+
+impl Ascii {
+    pub fn new(mut machine: Machine) -> Self {
+        machine.wait_on_input();
+        Ascii { machine }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    /// queue `text` as character codes, with `\r\n` sent as a plain `\n`
+    pub fn send_text(&mut self, text: &str) -> Result<(), AsciiError> {
+        if let Some(c) = text.chars().find(|c| !c.is_ascii()) {
+            return Err(AsciiError::NonAscii(c));
+        }
+        for c in text.replace("\r\n", "\n").bytes() {
+            self.machine.add_input(c as i128);
+        }
+        Ok(())
+    }
+
+    /// queue `line`, adding the terminating newline if it doesn't have one
+    pub fn send_line(&mut self, line: &str) -> Result<(), AsciiError> {
+        self.send_text(line)?;
+        if !line.ends_with('\n') {
+            self.machine.add_input(b'\n' as i128);
+        }
+        Ok(())
+    }
+
+    /// run until the program halts or wants more input, returning what it
+    /// printed in the meantime
+    pub fn run(&mut self) -> Result<(Status, AsciiOutput), MachineError> {
+        let status = self.machine.try_run()?;
+        let output = AsciiOutput::decode(&self.machine.output);
+        self.machine.output.clear();
+        Ok((status, output))
+    }
+
+    /// run an interactive session: the program's text is written to `output`
+    /// as it's produced, and each time the program wants input a line is read
+    /// from `input`. Values that aren't ASCII are written on a line of their
+    /// own. Stops when the program halts or exhausts its budget, or waits
+    /// after `input` is exhausted.
+    pub fn interact(
+        &mut self,
+        mut input: impl BufRead,
+        mut output: impl Write,
+    ) -> Result<Status, AsciiError> {
+        loop {
+            let (status, printed) = self.run()?;
+            output.write_all(printed.text.as_bytes())?;
+            for value in printed.values {
+                writeln!(output, "{}", value)?;
+            }
+            output.flush()?;
+            if status != Status::Waiting {
+                return Ok(status);
+            }
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(status);
+            }
+            self.send_line(&line)?;
+        }
+    }
+
+    /// `interact` on stdin and stdout
+    pub fn interact_stdio(&mut self) -> Result<Status, AsciiError> {
+        self.interact(io::stdin().lock(), io::stdout().lock())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::assembler::assemble;
+
+    // prints "> ", echoes one line back, then prints its length and halts
+    fn echo() -> Ascii {
+        let src = "
+                    OUT #62
+                    OUT #32
+            read:   IN char
+                    OUT char
+                    ADD count, #1, count
+                    EQ char, #10, done
+                    JF done, #read
+                    ADD count, #999, count
+                    OUT count
+                    HLT
+            char:   .data 0
+            count:  .data 0
+            done:   .data 0
+        ";
+        Ascii::new(Machine::new(&assemble(src).unwrap(), vec![]))
+    }
+
+    #[test]
+    fn test_decode() {
+        let decoded = AsciiOutput::decode(&[104, 105, 10, 1234, -1, 33]);
+        assert!(decoded.text == "hi\n!");
+        assert!(decoded.values == vec![1234, -1]);
+    }
+
+    #[test]
+    fn test_send_and_run() {
+        let mut ascii = echo();
+        let (status, output) = ascii.run().unwrap();
+        assert!(status == Status::Waiting && output.text == "> ");
+        ascii.send_line("hey").unwrap();
+        let (status, output) = ascii.run().unwrap();
+        assert!(status == Status::Halted);
+        assert!(output.text == "hey\n" && output.values == vec![1003]);
+
+        let mut ascii = echo();
+        ascii.send_text("a\r\n").unwrap();
+        assert!(ascii.machine().input == vec![97, 10]);
+        assert!(matches!(
+            ascii.send_line("é"),
+            Err(AsciiError::NonAscii('é'))
+        ));
+    }
+
+    #[test]
+    fn test_interact() {
+        let mut ascii = echo();
+        let mut out = vec![];
+        let status = ascii.interact(&b"oh\nignored\n"[..], &mut out).unwrap();
+        assert!(status == Status::Halted);
+        assert!(String::from_utf8(out).unwrap() == "> oh\n1002\n");
+
+        let mut ascii = echo();
+        let mut out = vec![];
+        assert!(ascii.interact(&b""[..], &mut out).unwrap() == Status::Waiting);
+        assert!(out == b"> ");
+    }
+}
@@ -0,0 +1,523 @@
+//! An assembler for the mnemonic form of Intcode that the disassembler emits.
+//!
+//! ```text
+//! ; read a number and print its double
+//! start:  IN value
+//!         MUL value, #2, value
+//!         OUT value
+//!         HLT
+//! value:  .data 0
+//! ```
+//!
+//! Operands are positional when written bare, immediate as `#operand` and
+//! relative as `@operand`. An operand is a number or a label, optionally
+//! offset by further numbers or labels (`table+1`, `end-start`). Comments run
+//! from `;` to the end of the line. A line may start with a numeric address,
+//! as in a disassembler listing, which must match the address it assembles to.
+use super::{Instruction, Parameter, ParameterMode};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// An error in the assembly source, with 1-based line and column numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl AssembleError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        AssembleError {
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Assemble `src` into a comma separated program that `Machine::new` can load
+pub fn assemble(src: &str) -> Result<String, AssembleError> {
+    let words = assemble_words(src)?;
+    Ok(words
+        .iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(","))
+}
+
+/// Assemble `src` into the words of the program
+pub fn assemble_words(src: &str) -> Result<Vec<i128>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = vec![];
+    let mut address = 0;
+    for (i, line) in src.lines().enumerate() {
+        let tokens = tokenize(line, i + 1)?;
+        let mut parser = LineParser {
+            tokens: &tokens,
+            pos: 0,
+            line: i + 1,
+            end_column: line.chars().count() + 1,
+        };
+        if let Some(statement) = parser.parse(address, &mut labels)? {
+            address += statement.size();
+            statements.push(statement);
+        }
+    }
+
+    let mut words = Vec::with_capacity(address);
+    for statement in &statements {
+        match statement {
+            Statement::Data(values) => {
+                for value in values {
+                    words.push(value.resolve(&labels)?);
+                }
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let parameters = operands
+                    .iter()
+                    .map(|o| Ok(Parameter::new(o.expr.resolve(&labels)?, o.mode)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                words.extend(build_instruction(mnemonic, &parameters).encode());
+            }
+        }
+    }
+    Ok(words)
+}
+
+/// the number of parameters each mnemonic takes, and which of them (if any)
+/// is written to
+fn signature(mnemonic: &str) -> Option<(usize, Option<usize>)> {
+    match mnemonic {
+        "ADD" | "MUL" | "LT" | "EQ" => Some((3, Some(2))),
+        "IN" => Some((1, Some(0))),
+        "OUT" | "ARB" => Some((1, None)),
+        "JT" | "JF" => Some((2, None)),
+        "HLT" => Some((0, None)),
+        _ => None,
+    }
+}
+
+fn build_instruction(mnemonic: &str, p: &[Parameter]) -> Instruction {
+    use Instruction::*;
+    match mnemonic {
+        "ADD" => Add(p[0], p[1], p[2]),
+        "MUL" => Mult(p[0], p[1], p[2]),
+        "IN" => Input(p[0]),
+        "OUT" => Output(p[0]),
+        "JT" => JumpTrue(p[0], p[1]),
+        "JF" => JumpFalse(p[0], p[1]),
+        "LT" => LessThan(p[0], p[1], p[2]),
+        "EQ" => Equal(p[0], p[1], p[2]),
+        "ARB" => AdjustRelativeBase(p[0]),
+        "HLT" => Halt,
+        _ => unreachable!("mnemonic {} was validated by the parser", mnemonic),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(i128),
+    Directive(String),
+    Comma,
+    Colon,
+    Hash,
+    At,
+    Plus,
+    Minus,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TokenKind::*;
+        match self {
+            Ident(s) => write!(f, "'{}'", s),
+            Number(n) => write!(f, "'{}'", n),
+            Directive(d) => write!(f, "'.{}'", d),
+            Comma => write!(f, "','"),
+            Colon => write!(f, "':'"),
+            Hash => write!(f, "'#'"),
+            At => write!(f, "'@'"),
+            Plus => write!(f, "'+'"),
+            Minus => write!(f, "'-'"),
+        }
+    }
+}
+
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn tokenize(line: &str, line_no: usize) -> Result<Vec<Token>, AssembleError> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let single = match c {
+            ';' => break,
+            ',' => Some(TokenKind::Comma),
+            ':' => Some(TokenKind::Colon),
+            '#' => Some(TokenKind::Hash),
+            '@' => Some(TokenKind::At),
+            '+' => Some(TokenKind::Plus),
+            '-' => Some(TokenKind::Minus),
+            _ => None,
+        };
+        if let Some(kind) = single {
+            tokens.push(Token { kind, column });
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = if c == '.' { i + 1 } else { i };
+        let mut end = start;
+        while end < chars.len() && is_ident_char(chars[end]) {
+            end += 1;
+        }
+        if end == start {
+            return Err(AssembleError::new(
+                line_no,
+                column,
+                format!("unexpected character '{}'", c),
+            ));
+        }
+        let text = chars[start..end].iter().collect::<String>();
+        let kind = if c == '.' {
+            TokenKind::Directive(text)
+        } else if c.is_ascii_digit() {
+            let n = text.parse::<i128>().map_err(|_| {
+                AssembleError::new(line_no, column, format!("invalid number '{}'", text))
+            })?;
+            TokenKind::Number(n)
+        } else {
+            TokenKind::Ident(text)
+        };
+        tokens.push(Token { kind, column });
+        i = end;
+    }
+    Ok(tokens)
+}
+
+enum Atom {
+    Number(i128),
+    Label(String),
+}
+
+/// a sum of signed numbers and labels, resolved once all labels are known
+struct Expr {
+    terms: Vec<(bool, Atom, usize)>,
+    line: usize,
+}
+
+impl Expr {
+    fn resolve(&self, labels: &HashMap<String, usize>) -> Result<i128, AssembleError> {
+        let mut total: i128 = 0;
+        for (negative, atom, column) in &self.terms {
+            let value = match atom {
+                Atom::Number(n) => *n,
+                Atom::Label(name) => match labels.get(name) {
+                    Some(&address) => address as i128,
+                    None => {
+                        return Err(AssembleError::new(
+                            self.line,
+                            *column,
+                            format!("undefined label '{}'", name),
+                        ))
+                    }
+                },
+            };
+            let next = if *negative {
+                total.checked_sub(value)
+            } else {
+                total.checked_add(value)
+            };
+            total = next.ok_or_else(|| {
+                AssembleError::new(self.line, *column, "value out of range".to_owned())
+            })?;
+        }
+        Ok(total)
+    }
+}
+
+struct Operand {
+    mode: ParameterMode,
+    expr: Expr,
+    column: usize,
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Data(Vec<Expr>),
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+        }
+    }
+}
+
+struct LineParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    line: usize,
+    end_column: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn peek(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|t| t.column)
+            .unwrap_or(self.end_column)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, AssembleError> {
+        Err(AssembleError::new(self.line, self.column(), message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, AssembleError> {
+        match self.peek() {
+            Some(kind) => self.error(format!("expected {}, found {}", expected, kind)),
+            None => self.error(format!("expected {}, found end of line", expected)),
+        }
+    }
+
+    fn parse(
+        &mut self,
+        address: usize,
+        labels: &mut HashMap<String, usize>,
+    ) -> Result<Option<Statement>, AssembleError> {
+        // an address column, as in a disassembler listing
+        if let Some(&TokenKind::Number(listed)) = self.peek() {
+            if listed != address as i128 {
+                return self.error(format!(
+                    "listed address {} doesn't match assembled address {}",
+                    listed, address
+                ));
+            }
+            self.pos += 1;
+        }
+
+        while let (
+            Some(TokenKind::Ident(name)),
+            Some(Token {
+                kind: TokenKind::Colon,
+                ..
+            }),
+        ) = (self.peek(), self.tokens.get(self.pos + 1))
+        {
+            if labels.insert(name.clone(), address).is_some() {
+                return self.error(format!("label '{}' is already defined", name));
+            }
+            self.pos += 2;
+        }
+
+        let statement = match self.peek() {
+            None => return Ok(None),
+            Some(TokenKind::Directive(directive)) => {
+                if directive != "data" {
+                    return self.error(format!("unknown directive '.{}'", directive));
+                }
+                self.pos += 1;
+                Statement::Data(self.parse_list(|p| p.parse_expr())?)
+            }
+            Some(TokenKind::Ident(mnemonic)) => {
+                let mnemonic = mnemonic.to_ascii_uppercase();
+                let (count, destination) = match signature(&mnemonic) {
+                    Some(signature) => signature,
+                    None => return self.error(format!("unknown mnemonic '{}'", mnemonic)),
+                };
+                let mnemonic_column = self.column();
+                self.pos += 1;
+                let operands = if count == 0 {
+                    vec![]
+                } else {
+                    self.parse_list(|p| p.parse_operand())?
+                };
+                if operands.len() != count {
+                    return Err(AssembleError::new(
+                        self.line,
+                        mnemonic_column,
+                        format!(
+                            "{} takes {} operand(s), found {}",
+                            mnemonic,
+                            count,
+                            operands.len()
+                        ),
+                    ));
+                }
+                if let Some(destination) = destination {
+                    let operand = &operands[destination];
+                    if operand.mode == ParameterMode::Immediate {
+                        return Err(AssembleError::new(
+                            self.line,
+                            operand.column,
+                            format!("the destination of {} cannot be immediate", mnemonic),
+                        ));
+                    }
+                }
+                Statement::Instruction { mnemonic, operands }
+            }
+            Some(_) => return self.unexpected("a label, mnemonic or directive"),
+        };
+
+        if self.peek().is_some() {
+            return self.unexpected("end of line");
+        }
+        Ok(Some(statement))
+    }
+
+    fn parse_list<T>(
+        &mut self,
+        parse_item: impl Fn(&mut Self) -> Result<T, AssembleError>,
+    ) -> Result<Vec<T>, AssembleError> {
+        let mut items = vec![parse_item(self)?];
+        while let Some(TokenKind::Comma) = self.peek() {
+            self.pos += 1;
+            items.push(parse_item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, AssembleError> {
+        let column = self.column();
+        let mode = match self.peek() {
+            Some(TokenKind::Hash) => ParameterMode::Immediate,
+            Some(TokenKind::At) => ParameterMode::Relative,
+            _ => ParameterMode::Positional,
+        };
+        if mode != ParameterMode::Positional {
+            self.pos += 1;
+        }
+        let expr = self.parse_expr()?;
+        Ok(Operand { mode, expr, column })
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, AssembleError> {
+        let mut terms = vec![];
+        let mut negative = false;
+        if let Some(TokenKind::Minus) = self.peek() {
+            negative = true;
+            self.pos += 1;
+        }
+        loop {
+            let column = self.column();
+            let atom = match self.peek() {
+                Some(TokenKind::Number(n)) => Atom::Number(*n),
+                Some(TokenKind::Ident(name)) => Atom::Label(name.clone()),
+                _ => return self.unexpected("a number or label"),
+            };
+            self.pos += 1;
+            terms.push((negative, atom, column));
+            negative = match self.peek() {
+                Some(TokenKind::Plus) => false,
+                Some(TokenKind::Minus) => true,
+                _ => break,
+            };
+            self.pos += 1;
+        }
+        Ok(Expr {
+            terms,
+            line: self.line,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::Machine;
+
+    #[test]
+    fn test_assemble_labels() {
+        let src = "
+            ; print the numbers 3, 2, 1
+            loop:   OUT count           ; current value
+                    ADD count, #-1, count
+                    JT count, #loop
+                    HLT
+            count:  .data 3
+        ";
+        let program = assemble(src).unwrap();
+        assert!(program == "4,10,1001,10,-1,10,1005,10,0,99,3");
+        let mut machine = Machine::new(&program, vec![]);
+        machine.run();
+        assert!(machine.output == vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_assemble_relative_and_offsets() {
+        let src = "
+            ARB #table+1
+            OUT @0
+            OUT @1
+            HLT
+            table: .data 10, 20, 30, end-table
+            end:
+        ";
+        let mut machine = Machine::new(&assemble(src).unwrap(), vec![]);
+        machine.run();
+        assert!(machine.output == vec![20, 30]);
+        assert!(machine.memory[10] == 4);
+    }
+
+    #[test]
+    fn test_round_trip_listing() {
+        let program = "109,19,21101,3,4,-2,204,-2,1105,1,13,55,99,99";
+        let machine = Machine::new(program, vec![]);
+        let listing = machine.disassemble().to_string();
+        assert!(assemble(&listing).unwrap() == program);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |src: &str| {
+            let e = assemble(src).err().unwrap();
+            (e.line, e.column)
+        };
+        assert!(error("HLT\nADD 1, 2") == (2, 1));
+        assert!(error("  ADD 1, 2, #3") == (1, 13));
+        assert!(error("JT #1, #nowhere") == (1, 9));
+        assert!(error("a: HLT\na: HLT") == (2, 1));
+        assert!(error("FOO 1") == (1, 1));
+        assert!(error(".word 1") == (1, 1));
+        assert!(error("OUT 1 2") == (1, 7));
+        assert!(error("OUT $") == (1, 5));
+        assert!(error("0 HLT\n0 HLT") == (2, 1));
+        assert!(
+            assemble("OUT").err().unwrap().message
+                == "expected a number or label, found end of line"
+        );
+    }
+}
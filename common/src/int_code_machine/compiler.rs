@@ -0,0 +1,937 @@
+//! A compiler from a small structured language to Intcode.
+//!
+//! ```text
+//! // print the factorial of each input, until a 0
+//! fn fact(n) {
+//!     if n < 2 {
+//!         return 1;
+//!     }
+//!     return n * fact(n - 1);
+//! }
+//!
+//! let n = input();
+//! while n != 0 {
+//!     output(fact(n));
+//!     n = input();
+//! }
+//! ```
+//!
+//! A program is a list of functions and statements. The statements run in
+//! order, and then the program halts. There are `let` declarations,
+//! assignments, `if` and `else`, `while`, `return`, function calls,
+//! `input()` and `output(value)`. The operators are `+`, `-` and `*`, the
+//! comparisons, which give 1 or 0, `!`, and short-circuiting `&&` and `||`.
+//! Intcode has no division, so neither does the language. Comments run from
+//! `//` to the end of the line.
+//!
+//! Variables declared outside of any block or function are globals, which
+//! start as 0 and are visible to functions too. Everything else is local to
+//! its block. Each call gets a frame on a stack addressed through the
+//! relative base, so functions can recurse. A frame holds the return address
+//! at `@0`, then the arguments, locals and temporaries. Results are passed
+//! back in a fixed word, and a function that ends without `return` returns 0.
+//!
+//! The compiler emits source for the `assembler`, which
+//! `compile_to_assembly` returns for inspection.
+
+use super::assembler;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write as _;
+
+/// An error in the source, with 1-based line and column numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for CompileError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error<T>(self, message: String) -> Result<T, CompileError> {
+        Err(CompileError {
+            line: self.line,
+            column: self.column,
+            message,
+        })
+    }
+}
+
+/// Compile `src` into a comma separated program that `Machine::new` can load
+pub fn compile(src: &str) -> Result<String, CompileError> {
+    let assembly = compile_to_assembly(src)?;
+    Ok(assembler::assemble(&assembly).expect("the compiler emits valid assembly"))
+}
+
+/// Compile `src` into assembly for `assembler::assemble`
+pub fn compile_to_assembly(src: &str) -> Result<String, CompileError> {
+    let tokens = tokenize(src)?;
+    let end = match tokens.last() {
+        Some(token) => Pos {
+            line: token.pos.line,
+            column: token.pos.column + token.kind.to_string().len() - 2,
+        },
+        None => Pos { line: 1, column: 1 },
+    };
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        end,
+    };
+    let (functions, main) = parser.program()?;
+    Codegen::new(&functions)?.program(&functions, &main)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(i128),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(s) => write!(f, "'{}'", s),
+            TokenKind::Number(n) => write!(f, "'{}'", n),
+            TokenKind::Symbol(s) => write!(f, "'{}'", s),
+        }
+    }
+}
+
+struct Token {
+    kind: TokenKind,
+    pos: Pos,
+}
+
+/// the symbols, longest first so that `<=` isn't read as `<` then `=`
+const SYMBOLS: [&str; 19] = [
+    "<=", ">=", "==", "!=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "<", ">",
+    "!",
+];
+
+const KEYWORDS: [&str; 8] = [
+    "fn", "let", "if", "else", "while", "return", "input", "output",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut c = 0;
+        while c < chars.len() {
+            let pos = Pos {
+                line: i + 1,
+                column: c + 1,
+            };
+            let rest = &line[line.char_indices().nth(c).map_or(line.len(), |(b, _)| b)..];
+            if rest.starts_with("//") {
+                break;
+            }
+            if chars[c].is_whitespace() {
+                c += 1;
+                continue;
+            }
+            if let Some(&symbol) = SYMBOLS.iter().find(|&&s| rest.starts_with(s)) {
+                tokens.push(Token {
+                    kind: TokenKind::Symbol(symbol),
+                    pos,
+                });
+                c += symbol.len();
+                continue;
+            }
+            let start = c;
+            while c < chars.len() && (chars[c].is_ascii_alphanumeric() || chars[c] == '_') {
+                c += 1;
+            }
+            if c == start {
+                return pos.error(format!("unexpected character '{}'", chars[c]));
+            }
+            let text = chars[start..c].iter().collect::<String>();
+            let kind = if chars[start].is_ascii_digit() {
+                match text.parse() {
+                    Ok(n) => TokenKind::Number(n),
+                    Err(_) => return pos.error(format!("invalid number '{}'", text)),
+                }
+            } else {
+                TokenKind::Ident(text)
+            };
+            tokens.push(Token { kind, pos });
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(i128),
+    Var(String, Pos),
+    Input,
+    Call(String, Vec<Expr>, Pos),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// whether evaluating the expression could read input or change a
+    /// global
+    fn has_effects(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Var(..) => false,
+            Expr::Input | Expr::Call(..) => true,
+            Expr::Negate(e) | Expr::Not(e) => e.has_effects(),
+            Expr::Binary(_, a, b) => a.has_effects() || b.has_effects(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Stmt {
+    Let(String, Expr),
+    Assign(String, Expr, Pos),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>, Pos),
+    Output(Expr),
+    Expr(Expr),
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    pos: Pos,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// where the end of the source is, for errors there
+    end: Pos,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn here(&self) -> Pos {
+        self.tokens.get(self.pos).map_or(self.end, |t| t.pos)
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, CompileError> {
+        match self.peek() {
+            Some(kind) => self
+                .here()
+                .error(format!("expected {}, found {}", expected, kind)),
+            None => self
+                .here()
+                .error(format!("expected {}, found end of input", expected)),
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Ident(s)) if s == keyword)
+    }
+
+    /// skip `symbol` if it's next
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), CompileError> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", symbol))
+        }
+    }
+
+    fn name(&mut self) -> Result<(String, Pos), CompileError> {
+        let pos = self.here();
+        match self.peek() {
+            Some(TokenKind::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                self.pos += 1;
+                Ok((name.clone(), pos))
+            }
+            _ => self.unexpected("a name"),
+        }
+    }
+
+    fn program(&mut self) -> Result<(Vec<Function>, Vec<Stmt>), CompileError> {
+        let mut functions = vec![];
+        let mut main = vec![];
+        while self.peek().is_some() {
+            if self.is_keyword("fn") {
+                functions.push(self.function()?);
+            } else {
+                main.push(self.statement()?);
+            }
+        }
+        Ok((functions, main))
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        self.pos += 1;
+        let (name, pos) = self.name()?;
+        self.expect("(")?;
+        let mut params = vec![];
+        if !self.eat(")") {
+            loop {
+                let (param, pos) = self.name()?;
+                if params.contains(&param) {
+                    return pos.error(format!("parameter '{}' is already defined", param));
+                }
+                params.push(param);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let body = self.block()?;
+        Ok(Function {
+            name,
+            params,
+            body,
+            pos,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut statements = vec![];
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.unexpected("'}'");
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let pos = self.here();
+        let keyword = match self.peek() {
+            Some(TokenKind::Ident(word)) => word.as_str(),
+            _ => "",
+        };
+        let statement = match keyword {
+            "let" => {
+                self.pos += 1;
+                let (name, _) = self.name()?;
+                self.expect("=")?;
+                Stmt::Let(name, self.expr()?)
+            }
+            "if" => return self.if_statement(),
+            "while" => {
+                self.pos += 1;
+                let condition = self.expr()?;
+                return Ok(Stmt::While(condition, self.block()?));
+            }
+            "return" => {
+                self.pos += 1;
+                let value = match self.is_symbol(";") {
+                    true => None,
+                    false => Some(self.expr()?),
+                };
+                Stmt::Return(value, pos)
+            }
+            "output" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let value = self.expr()?;
+                self.expect(")")?;
+                Stmt::Output(value)
+            }
+            _ => match self.tokens.get(self.pos + 1).map(|t| &t.kind) {
+                Some(TokenKind::Symbol("=")) => {
+                    let (name, pos) = self.name()?;
+                    self.pos += 1;
+                    Stmt::Assign(name, self.expr()?, pos)
+                }
+                _ => Stmt::Expr(self.expr()?),
+            },
+        };
+        self.expect(";")?;
+        Ok(statement)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, CompileError> {
+        self.pos += 1;
+        let condition = self.expr()?;
+        let then = self.block()?;
+        let otherwise = if !self.is_keyword("else") {
+            vec![]
+        } else {
+            self.pos += 1;
+            if self.is_keyword("if") {
+                vec![self.if_statement()?]
+            } else {
+                self.block()?
+            }
+        };
+        Ok(Stmt::If(condition, then, otherwise))
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    /// parse operators of precedence `level` and above
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        use BinOp::*;
+        const LEVELS: [&[(&str, BinOp)]; 5] = [
+            &[("||", Or)],
+            &[("&&", And)],
+            &[
+                ("==", Equal),
+                ("!=", NotEqual),
+                ("<=", LessEq),
+                (">=", GreaterEq),
+                ("<", Less),
+                (">", Greater),
+            ],
+            &[("+", Add), ("-", Sub)],
+            &[("*", Mul)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, op)) = LEVELS[level].iter().find(|(s, _)| self.is_symbol(s)) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if let Some(&TokenKind::Number(n)) = self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Number(n));
+        }
+        if self.is_keyword("input") {
+            self.pos += 1;
+            self.expect("(")?;
+            self.expect(")")?;
+            return Ok(Expr::Input);
+        }
+        let (name, pos) = match self.peek() {
+            Some(TokenKind::Ident(_)) => self.name()?,
+            _ => return self.unexpected("an expression"),
+        };
+        if !self.eat("(") {
+            return Ok(Expr::Var(name, pos));
+        }
+        let mut args = vec![];
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Expr::Call(name, args, pos))
+    }
+}
+
+/// the word functions leave their result in
+const RESULT: &str = "result";
+
+struct Codegen {
+    assembly: String,
+    labels: usize,
+    /// the number of parameters of each function
+    arities: HashMap<String, usize>,
+    globals: Vec<String>,
+    /// the frame offsets of the locals in each enclosing block, innermost
+    /// last; empty outside of any block or function
+    scopes: Vec<HashMap<String, usize>>,
+    /// the first frame offset not in use
+    depth: usize,
+    in_function: bool,
+}
+
+impl Codegen {
+    fn new(functions: &[Function]) -> Result<Self, CompileError> {
+        let mut arities = HashMap::new();
+        for function in functions {
+            if arities
+                .insert(function.name.clone(), function.params.len())
+                .is_some()
+            {
+                return function
+                    .pos
+                    .error(format!("function '{}' is already defined", function.name));
+            }
+        }
+        Ok(Codegen {
+            assembly: String::new(),
+            labels: 0,
+            arities,
+            globals: vec![],
+            scopes: vec![],
+            depth: 0,
+            in_function: false,
+        })
+    }
+
+    fn program(mut self, functions: &[Function], main: &[Stmt]) -> Result<String, CompileError> {
+        let mut seen = HashSet::new();
+        for statement in main {
+            if let Stmt::Let(name, _) = statement {
+                if seen.insert(name.clone()) {
+                    self.globals.push(name.clone());
+                }
+            }
+        }
+        self.emit("ARB #stack");
+        self.statements(main)?;
+        self.emit("HLT");
+        for function in functions {
+            self.function(function)?;
+        }
+        self.label(RESULT);
+        self.emit(".data 0");
+        for global in std::mem::take(&mut self.globals) {
+            self.label(&format!("g_{}", global));
+            self.emit(".data 0");
+        }
+        self.label("stack");
+        Ok(self.assembly)
+    }
+
+    fn emit(&mut self, line: &str) {
+        // writing to a String can't fail
+        writeln!(self.assembly, "        {}", line).unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.assembly, "{}:", label).unwrap();
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    /// a new temporary in the current frame
+    fn temp(&mut self) -> String {
+        self.depth += 1;
+        format!("@{}", self.depth - 1)
+    }
+
+    fn variable(&self, name: &str, pos: Pos) -> Result<String, CompileError> {
+        if let Some(offset) = self.scopes.iter().rev().find_map(|s| s.get(name)) {
+            return Ok(format!("@{}", offset));
+        }
+        if self.globals.iter().any(|g| g == name) {
+            return Ok(format!("g_{}", name));
+        }
+        pos.error(format!("unknown variable '{}'", name))
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.label(&format!("f_{}", function.name));
+        let params = function
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| (param.clone(), i + 1))
+            .collect();
+        self.scopes = vec![params];
+        self.depth = function.params.len() + 1;
+        self.in_function = true;
+        self.statements(&function.body)?;
+        self.emit(&format!("ADD #0, #0, {}", RESULT));
+        self.emit("JT #1, @0");
+        self.scopes.clear();
+        self.in_function = false;
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        let depth = self.depth;
+        self.scopes.push(HashMap::new());
+        self.statements(statements)?;
+        self.scopes.pop();
+        self.depth = depth;
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        for statement in statements {
+            let depth = self.depth;
+            self.statement(statement)?;
+            // only a new local outlives its statement
+            if !matches!(statement, Stmt::Let(..)) || self.scopes.is_empty() {
+                self.depth = depth;
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), CompileError> {
+        match statement {
+            Stmt::Let(name, value) => match self.scopes.last_mut() {
+                None => self.compile_into(value, &format!("g_{}", name))?,
+                Some(_) => {
+                    let slot = self.depth;
+                    self.depth += 1;
+                    self.compile_into(value, &format!("@{}", slot))?;
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert(name.clone(), slot);
+                    }
+                }
+            },
+            Stmt::Assign(name, value, pos) => {
+                let destination = self.variable(name, *pos)?;
+                self.compile_into(value, &destination)?;
+            }
+            Stmt::If(condition, then, otherwise) => {
+                let (other, end) = (self.new_label(), self.new_label());
+                let check = self.operand(condition)?;
+                self.emit(&format!("JF {}, #{}", check, other));
+                self.block(then)?;
+                self.emit(&format!("JT #1, #{}", end));
+                self.label(&other);
+                self.block(otherwise)?;
+                self.label(&end);
+            }
+            Stmt::While(condition, body) => {
+                let (top, end) = (self.new_label(), self.new_label());
+                self.label(&top);
+                let check = self.operand(condition)?;
+                self.emit(&format!("JF {}, #{}", check, end));
+                self.block(body)?;
+                self.emit(&format!("JT #1, #{}", top));
+                self.label(&end);
+            }
+            Stmt::Return(value, pos) => {
+                if !self.in_function {
+                    return pos.error("return outside of a function".to_owned());
+                }
+                match value {
+                    Some(value) => self.compile_into(value, RESULT)?,
+                    None => self.emit(&format!("ADD #0, #0, {}", RESULT)),
+                }
+                self.emit("JT #1, @0");
+            }
+            Stmt::Output(value) => {
+                let value = self.operand(value)?;
+                self.emit(&format!("OUT {}", value));
+            }
+            Stmt::Expr(Expr::Call(name, args, pos)) => self.call(name, args, *pos)?,
+            Stmt::Expr(value) => {
+                let temp = self.temp();
+                self.compile_into(value, &temp)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// an operand for the value of `expr`, which is computed into a
+    /// temporary unless it's a number or variable
+    fn operand(&mut self, expr: &Expr) -> Result<String, CompileError> {
+        match expr {
+            Expr::Number(n) => Ok(format!("#{}", n)),
+            Expr::Var(name, pos) => self.variable(name, *pos),
+            _ => {
+                let temp = self.temp();
+                self.compile_into(expr, &temp)?;
+                Ok(temp)
+            }
+        }
+    }
+
+    /// compute `expr` into the operand `destination`
+    fn compile_into(&mut self, expr: &Expr, destination: &str) -> Result<(), CompileError> {
+        let depth = self.depth;
+        match expr {
+            Expr::Number(_) | Expr::Var(..) => {
+                let value = self.operand(expr)?;
+                self.emit(&format!("ADD {}, #0, {}", value, destination));
+            }
+            Expr::Input => self.emit(&format!("IN {}", destination)),
+            Expr::Call(name, args, pos) => {
+                self.call(name, args, *pos)?;
+                self.emit(&format!("ADD {}, #0, {}", RESULT, destination));
+            }
+            Expr::Negate(value) => {
+                let value = self.operand(value)?;
+                self.emit(&format!("MUL {}, #-1, {}", value, destination));
+            }
+            Expr::Not(value) => {
+                let value = self.operand(value)?;
+                self.emit(&format!("EQ {}, #0, {}", value, destination));
+            }
+            Expr::Binary(op @ (BinOp::And | BinOp::Or), a, b) => {
+                let (short, end) = (self.new_label(), self.new_label());
+                let jump = if *op == BinOp::And { "JF" } else { "JT" };
+                let a = self.operand(a)?;
+                self.emit(&format!("{} {}, #{}", jump, a, short));
+                let b = self.operand(b)?;
+                // b as 1 or 0
+                self.emit(&format!("EQ {}, #0, {}", b, destination));
+                self.emit(&format!("EQ {}, #0, {}", destination, destination));
+                self.emit(&format!("JT #1, #{}", end));
+                self.label(&short);
+                let value = if *op == BinOp::And { 0 } else { 1 };
+                self.emit(&format!("ADD #{}, #0, {}", value, destination));
+                self.label(&end);
+            }
+            Expr::Binary(op, a, b) => {
+                // a variable could be changed while computing `b`, so its
+                // value is taken first
+                let a = match **a {
+                    Expr::Var(..) if b.has_effects() => {
+                        let temp = self.temp();
+                        self.compile_into(a, &temp)?;
+                        temp
+                    }
+                    _ => self.operand(a)?,
+                };
+                let b = self.operand(b)?;
+                let (mnemonic, x, y, negated) = match op {
+                    BinOp::Add => ("ADD", &a, &b, false),
+                    BinOp::Mul => ("MUL", &a, &b, false),
+                    BinOp::Less => ("LT", &a, &b, false),
+                    BinOp::Greater => ("LT", &b, &a, false),
+                    BinOp::GreaterEq => ("LT", &a, &b, true),
+                    BinOp::LessEq => ("LT", &b, &a, true),
+                    BinOp::Equal => ("EQ", &a, &b, false),
+                    BinOp::NotEqual => ("EQ", &a, &b, true),
+                    BinOp::Sub => {
+                        let temp = self.temp();
+                        self.emit(&format!("MUL {}, #-1, {}", b, temp));
+                        self.emit(&format!("ADD {}, {}, {}", a, temp, destination));
+                        self.depth = depth;
+                        return Ok(());
+                    }
+                    BinOp::And | BinOp::Or => unreachable!("handled above"),
+                };
+                if negated {
+                    let temp = self.temp();
+                    self.emit(&format!("{} {}, {}, {}", mnemonic, x, y, temp));
+                    self.emit(&format!("EQ {}, #0, {}", temp, destination));
+                } else {
+                    self.emit(&format!("{} {}, {}, {}", mnemonic, x, y, destination));
+                }
+            }
+        }
+        self.depth = depth;
+        Ok(())
+    }
+
+    /// call `name`, giving it a frame just past the ones in use
+    fn call(&mut self, name: &str, args: &[Expr], pos: Pos) -> Result<(), CompileError> {
+        match self.arities.get(name) {
+            None => return pos.error(format!("unknown function '{}'", name)),
+            Some(&arity) if arity != args.len() => {
+                return pos.error(format!(
+                    "'{}' takes {} argument(s), found {}",
+                    name,
+                    arity,
+                    args.len()
+                ))
+            }
+            Some(_) => {}
+        }
+        let base = self.depth;
+        for (i, arg) in args.iter().enumerate() {
+            // the arguments computed so far are safe below `depth`
+            self.depth = base + i + 2;
+            self.compile_into(arg, &format!("@{}", base + i + 1))?;
+        }
+        let back = self.new_label();
+        self.emit(&format!("ADD #{}, #0, @{}", back, base));
+        self.emit(&format!("ARB #{}", base));
+        self.emit(&format!("JT #1, #f_{}", name));
+        self.label(&back);
+        self.emit(&format!("ARB #-{}", base));
+        self.depth = base;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::Machine;
+
+    fn run(src: &str, input: Vec<i128>) -> Vec<i128> {
+        let program = compile(src).unwrap_or_else(|e| panic!("{}", e));
+        let mut machine = Machine::new(&program, input);
+        machine.run();
+        machine.output
+    }
+
+    fn error(src: &str) -> (usize, usize, String) {
+        let e = compile(src).err().unwrap();
+        (e.line, e.column, e.message)
+    }
+
+    #[test]
+    fn test_expressions() {
+        let src = "
+            let a = input();
+            let b = 7;
+            output(a + b * 2);      // precedence
+            output((a - b) * -2);
+            output(a < b);
+            output(a >= b);
+            output(a == 5 && b != 5);
+            output(!(a > 9) || input());
+        ";
+        assert!(run(src, vec![5]) == vec![19, 4, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        // sums the inputs until a 0, then counts down the sum's sign
+        let src = "
+            let total = 0;
+            let n = input();
+            while n != 0 {
+                total = total + n;
+                n = input();
+            }
+            output(total);
+            if total > 0 {
+                output(1);
+            } else if total < 0 {
+                output(-1);
+            } else {
+                output(0);
+            }
+        ";
+        assert!(run(src, vec![3, 4, -2, 0]) == vec![5, 1]);
+        assert!(run(src, vec![-3, 0]) == vec![-3, -1]);
+        assert!(run(src, vec![0]) == vec![0, 0]);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let src = "
+            fn fib(n) {
+                if n < 2 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn fact(n) {
+                if n < 2 {
+                    return 1;
+                }
+                return n * fact(n - 1);
+            }
+
+            output(fib(input()));
+            output(fact(20));
+            output(fib(fact(3)) + fact(fib(4)));
+        ";
+        assert!(run(src, vec![15]) == vec![610, 2432902008176640000, 8 + 6]);
+    }
+
+    #[test]
+    fn test_scopes_and_globals() {
+        let src = "
+            let calls = 0;
+
+            // counts its calls in a global
+            fn count() {
+                calls = calls + 1;
+            }
+
+            fn sum3(a, b, c) {
+                let total = a + b;
+                if total > 0 {
+                    let total = 100;
+                }
+                count();
+                return total + c;
+            }
+
+            let x = 1;
+            output(x + sum3(x, 2, 3));
+            // the global is read before the call changes it
+            output(calls + sum3(calls, 0, 0));
+            // short-circuiting skips the call
+            output(0 && sum3(1, 1, 1));
+            output(calls);
+            output(count());
+        ";
+        assert!(run(src, vec![]) == vec![7, 2, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(error("output(y);").2 == "unknown variable 'y'");
+        let (line, column, _) = error("let x = 1;\nfn f() { return x + z; }\n");
+        assert!((line, column) == (2, 21));
+        assert!(error("fn f(a) {}\nf(1, 2);").2 == "'f' takes 1 argument(s), found 2");
+        assert!(error("g();").2 == "unknown function 'g'");
+        assert!(error("fn f() {}\nfn f() {}").0 == 2);
+        assert!(error("fn f(a, a) {}").1 == 9);
+        assert!(error("return 1;").2 == "return outside of a function");
+        assert!(error("let x = 1").2 == "expected ';', found end of input");
+        assert!(error("let = 1;") == (1, 5, "expected a name, found '='".to_owned()));
+        assert!(error("let x = 1 $ 2;").1 == 11);
+        assert!(error("while 1 { output(1); ").2 == "expected '}', found end of input");
+    }
+}
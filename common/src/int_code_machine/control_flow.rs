@@ -0,0 +1,402 @@
+use super::{Instruction, Machine, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// execution runs on into the next instruction, either because the block
+    /// ended where another starts or because a jump wasn't taken
+    Fallthrough,
+    /// a jump with an immediate target was taken
+    Jump,
+    /// where a call returns to. Compiled Intcode calls a function by storing
+    /// the return address to a relative address, then jumping to it
+    /// unconditionally, so an immediate value stored that way just before
+    /// such a jump is taken to be the return address.
+    Return,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that is only ever entered at the top and left at the
+/// bottom
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    /// each instruction with its address
+    pub instructions: Vec<(usize, Instruction)>,
+    pub successors: Vec<Edge>,
+    /// the block ends in a jump whose target is computed at run time, so
+    /// `successors` may be incomplete
+    pub unresolved: bool,
+    /// the block runs into a word that isn't a valid instruction
+    pub invalid: bool,
+}
+
+/// The basic blocks of a program, found by following execution from
+/// address 0 through fallthroughs, jumps with immediate targets and the
+/// return addresses of calls.
+///
+/// This is an analysis of memory as it is, so code the program writes for
+/// itself later, or only reaches through computed jumps, is missing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+}
+
+/// where a jump instruction can go
+enum Jump {
+    Never,
+    To(usize),
+    Computed,
+}
+
+/// the jump the instruction at `address` makes, and the address it can
+/// continue to without jumping, if any
+fn flow(address: usize, instruction: &Instruction) -> (Jump, Option<usize>) {
+    use Instruction::*;
+    let next = address + instruction.size();
+    let (condition, target, jump_if) = match instruction {
+        JumpTrue(condition, target) => (condition, target, true),
+        JumpFalse(condition, target) => (condition, target, false),
+        Halt => return (Jump::Never, None),
+        _ => return (Jump::Never, Some(next)),
+    };
+    // a jump on an immediate condition either always or never happens
+    let (may_jump, may_continue) = match condition.mode {
+        ParameterMode::Immediate => {
+            let jumps = (condition.value != 0) == jump_if;
+            (jumps, !jumps)
+        }
+        _ => (true, true),
+    };
+    let jump = match target.mode {
+        _ if !may_jump => Jump::Never,
+        ParameterMode::Immediate => match usize::try_from(target.value) {
+            Ok(target) => Jump::To(target),
+            Err(_) => Jump::Computed,
+        },
+        _ => Jump::Computed,
+    };
+    (jump, if may_continue { Some(next) } else { None })
+}
+
+/// the constant the instruction pushes on the stack, if it's an `ADD` or
+/// `MUL` of two immediate values writing to a relative address
+fn pushed_constant(instruction: &Instruction) -> Option<usize> {
+    use Instruction::*;
+    let value = match instruction {
+        Add(a, b, c) | Mult(a, b, c)
+            if a.mode == ParameterMode::Immediate
+                && b.mode == ParameterMode::Immediate
+                && c.mode == ParameterMode::Relative =>
+        {
+            match instruction {
+                Add(..) => a.value.checked_add(b.value),
+                _ => a.value.checked_mul(b.value),
+            }
+        }
+        _ => None,
+    };
+    value.and_then(|value| usize::try_from(value).ok())
+}
+
+fn decode(memory: &[i128], address: usize) -> Option<Instruction> {
+    Instruction::decode(memory.get(address..).unwrap_or(&[]), address).ok()
+}
+
+/// Build the control flow graph of `memory`. Jumps to immediate targets that
+/// aren't valid addresses, which would fail at run time, count as unresolved.
+pub fn control_flow_graph(memory: &[i128]) -> ControlFlowGraph {
+    // find every reachable instruction, and the addresses blocks start at
+    let mut instructions = BTreeMap::new();
+    // the constant pushed by the instruction before each address, and the
+    // return address of each call found
+    let mut pushes = BTreeMap::new();
+    let mut calls = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    let mut pending = vec![0];
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) {
+            continue;
+        }
+        let instruction = decode(memory, address);
+        instructions.insert(address, instruction);
+        let instruction = match instruction {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let (jump, next) = flow(address, &instruction);
+        let is_jump = matches!(
+            instruction,
+            Instruction::JumpTrue(..) | Instruction::JumpFalse(..)
+        );
+        if let Jump::To(target) = jump {
+            leaders.insert(target);
+            pending.push(target);
+            match pushes.get(&address) {
+                Some(&returns) if next.is_none() => {
+                    calls.insert(address, returns);
+                    leaders.insert(returns);
+                    pending.push(returns);
+                }
+                _ => {}
+            }
+        }
+        if let Some(pushed) = pushed_constant(&instruction) {
+            pushes.insert(address + instruction.size(), pushed);
+        }
+        if let Some(next) = next {
+            if is_jump {
+                leaders.insert(next);
+            }
+            pending.push(next);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in &leaders {
+        let mut block = BasicBlock {
+            start,
+            instructions: vec![],
+            successors: vec![],
+            unresolved: false,
+            invalid: false,
+        };
+        let mut address = start;
+        loop {
+            let instruction = match instructions[&address] {
+                Some(instruction) => instruction,
+                None => {
+                    block.invalid = true;
+                    break;
+                }
+            };
+            block.instructions.push((address, instruction));
+            let (jump, next) = flow(address, &instruction);
+            match jump {
+                Jump::Never => {}
+                Jump::To(target) => block.successors.push(Edge {
+                    target,
+                    kind: EdgeKind::Jump,
+                }),
+                Jump::Computed => block.unresolved = true,
+            }
+            if let Some(&target) = calls.get(&address) {
+                block.successors.push(Edge {
+                    target,
+                    kind: EdgeKind::Return,
+                });
+            }
+            match next {
+                Some(next) if leaders.contains(&next) || jump_or_halt(&instruction) => {
+                    block.successors.push(Edge {
+                        target: next,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                    break;
+                }
+                Some(next) => address = next,
+                None => break,
+            }
+        }
+        blocks.insert(start, block);
+    }
+    ControlFlowGraph { blocks }
+}
+
+fn jump_or_halt(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpTrue(..) | Instruction::JumpFalse(..) | Instruction::Halt
+    )
+}
+
+impl ControlFlowGraph {
+    /// the block containing the instruction at `address`, if it was reached
+    pub fn block_containing(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| {
+                block.instructions.iter().any(|(start, instruction)| {
+                    (*start..start + instruction.size()).contains(&address)
+                })
+            })
+    }
+
+    /// the graph in Graphviz DOT format, one node per block listing its
+    /// instructions. Taken jumps are solid edges, fallthroughs dashed and
+    /// returns labelled; blocks ending in a computed jump point at a shared
+    /// `unresolved` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        // writing to a String can't fail
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                write!(label, "{}: {}\\l", address, instruction).unwrap();
+            }
+            if block.invalid {
+                label.push_str("(invalid instruction)\\l");
+            }
+            writeln!(dot, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+            for edge in &block.successors {
+                let attributes = match edge.kind {
+                    EdgeKind::Jump => "style=solid",
+                    EdgeKind::Fallthrough => "style=dashed",
+                    EdgeKind::Return => "style=dashed, label=\"return\"",
+                };
+                writeln!(
+                    dot,
+                    "    b{} -> b{} [{}];",
+                    block.start, edge.target, attributes
+                )
+                .unwrap();
+            }
+        }
+        let unresolved = self.blocks.values().filter(|block| block.unresolved);
+        for (i, block) in unresolved.enumerate() {
+            if i == 0 {
+                writeln!(
+                    dot,
+                    "    unresolved [label=\"computed jump\", shape=ellipse];"
+                )
+                .unwrap();
+            }
+            writeln!(dot, "    b{} -> unresolved [style=dotted];", block.start).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl Machine {
+    /// build the control flow graph of the machine's current memory
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        control_flow_graph(&self.memory)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::assembler::assemble;
+
+    fn graph(src: &str) -> ControlFlowGraph {
+        Machine::new(&assemble(src).unwrap(), vec![]).control_flow_graph()
+    }
+
+    fn edges(block: &BasicBlock) -> Vec<(usize, EdgeKind)> {
+        block
+            .successors
+            .iter()
+            .map(|edge| (edge.target, edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_blocks() {
+        // counts down from its input, outputting each value
+        let cfg = graph(
+            "
+                    IN n
+            loop:   OUT n
+                    ADD n, #-1, n
+                    JT n, #loop
+                    HLT
+            n:      .data 0
+            ",
+        );
+        let starts = cfg.blocks.keys().cloned().collect::<Vec<_>>();
+        assert!(starts == vec![0, 2, 11]);
+        assert!(cfg.blocks[&0].instructions.len() == 1);
+        assert!(edges(&cfg.blocks[&0]) == vec![(2, EdgeKind::Fallthrough)]);
+        assert!(cfg.blocks[&2].instructions.len() == 3);
+        assert!(edges(&cfg.blocks[&2]) == vec![(2, EdgeKind::Jump), (11, EdgeKind::Fallthrough)]);
+        assert!(cfg.blocks[&11].successors.is_empty());
+        // the data word after the halt is never reached
+        assert!(cfg.block_containing(12).is_none());
+        assert!(cfg.block_containing(7).unwrap().start == 2);
+    }
+
+    #[test]
+    fn test_constant_conditions() {
+        // an unconditional jump over data, then a jump that's never taken
+        let cfg = graph(
+            "
+                    JT #1, #start
+                    .data 12345
+            start:  JF #1, #0
+                    HLT
+            ",
+        );
+        assert!(cfg.blocks.keys().cloned().collect::<Vec<_>>() == vec![0, 4, 7]);
+        assert!(edges(&cfg.blocks[&0]) == vec![(4, EdgeKind::Jump)]);
+        assert!(edges(&cfg.blocks[&4]) == vec![(7, EdgeKind::Fallthrough)]);
+    }
+
+    #[test]
+    fn test_calls() {
+        // calls `double` and outputs the result
+        let cfg = graph(
+            "
+                    ARB #100
+                    IN @1
+                    ADD #after, #0, @0
+                    JT #1, #double
+            after:  OUT @1
+                    HLT
+            double: MUL @1, #2, @1
+                    JT #1, @0
+            ",
+        );
+        assert!(cfg.blocks.keys().cloned().collect::<Vec<_>>() == vec![0, 11, 14]);
+        assert!(edges(&cfg.blocks[&0]) == vec![(14, EdgeKind::Jump), (11, EdgeKind::Return)]);
+        assert!(cfg.blocks[&14].unresolved);
+        assert!(cfg
+            .to_dot()
+            .contains("    b0 -> b11 [style=dashed, label=\"return\"];\n"));
+    }
+
+    #[test]
+    fn test_unresolved_and_invalid() {
+        // jumps to an address read from memory, then falls into garbage
+        let cfg = control_flow_graph(&[6, 3, 3, 42]);
+        assert!(cfg.blocks[&0].unresolved);
+        assert!(edges(&cfg.blocks[&0]) == vec![(3, EdgeKind::Fallthrough)]);
+        assert!(cfg.blocks[&3].invalid && cfg.blocks[&3].instructions.is_empty());
+
+        // a target too big for an address isn't cut down to a small one
+        let cfg = control_flow_graph(&[1105, 1, (1 << 64) + 3, 99]);
+        assert!(cfg.blocks[&0].unresolved && cfg.blocks[&0].successors.is_empty());
+        assert!(cfg.blocks.len() == 1);
+    }
+
+    #[test]
+    fn test_dot() {
+        let cfg = control_flow_graph(&[1005, 7, 6, 104, 1, 99, 99, 0]);
+        assert!(
+            cfg.to_dot()
+                == concat!(
+                    "digraph intcode {\n",
+                    "    node [shape=box, fontname=\"monospace\"];\n",
+                    "    b0 [label=\"0: JT 7, #6\\l\"];\n",
+                    "    b0 -> b6 [style=solid];\n",
+                    "    b0 -> b3 [style=dashed];\n",
+                    "    b3 [label=\"3: OUT #1\\l5: HLT\\l\"];\n",
+                    "    b6 [label=\"6: HLT\\l\"];\n",
+                    "}\n"
+                )
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use super::disassembler::{Item, ListingLine};
+use super::{Instruction, Machine, MachineError, Status, Step, StepOutcome};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const HEADER: &str = "intcode-coverage 1";
+
+/// How often each address was executed as an instruction, read as data and
+/// written, over one or more runs.
+///
+/// Executions count against the address an instruction starts at, and
+/// include the halt that ends a run. Reads and writes are those made by
+/// instructions, not instruction fetches.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub executed: BTreeMap<usize, u64>,
+    pub read: BTreeMap<usize, u64>,
+    pub written: BTreeMap<usize, u64>,
+}
+
+fn count(counts: &BTreeMap<usize, u64>, address: usize) -> u64 {
+    counts.get(&address).cloned().unwrap_or(0)
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    pub fn record(&mut self, step: &Step) {
+        *self.executed.entry(step.address).or_insert(0) += 1;
+        for read in step.reads() {
+            *self.read.entry(read.address).or_insert(0) += 1;
+        }
+        if let Some(write) = &step.write {
+            *self.written.entry(write.address).or_insert(0) += 1;
+        }
+    }
+
+    /// add the counts from `other`, e.g. a run on different input
+    pub fn merge(&mut self, other: &Coverage) {
+        for (mine, theirs) in [
+            (&mut self.executed, &other.executed),
+            (&mut self.read, &other.read),
+            (&mut self.written, &other.written),
+        ] {
+            for (&address, &n) in theirs {
+                *mine.entry(address).or_insert(0) += n;
+            }
+        }
+    }
+
+    /// the lines of a disassembly of `memory` guided by the coverage: every
+    /// executed address is decoded as an instruction, and otherwise
+    /// addresses only touched as data are shown as data, with the rest
+    /// decoded linearly as `disassemble` would
+    pub fn listing(&self, memory: &[i128]) -> Vec<ListingLine> {
+        let mut lines = vec![];
+        let mut address = 0;
+        while address < memory.len() {
+            let decoded = Instruction::decode(&memory[address..], address)
+                .ok()
+                .filter(|instruction| {
+                    let end = address + instruction.size();
+                    end <= memory.len()
+                        && (self.executed.contains_key(&address)
+                            || (!self.is_data(address)
+                                && self.executed.range(address + 1..end).next().is_none()))
+                });
+            let item = match decoded {
+                Some(instruction) => Item::Instruction(instruction),
+                None => Item::Data(memory[address]),
+            };
+            let size = match &item {
+                Item::Instruction(instruction) => instruction.size(),
+                Item::Data(_) => 1,
+            };
+            lines.push(ListingLine {
+                address,
+                item,
+                words: memory[address..address + size].to_vec(),
+            });
+            address += size;
+        }
+        lines
+    }
+
+    fn is_data(&self, address: usize) -> bool {
+        self.read.contains_key(&address) || self.written.contains_key(&address)
+    }
+
+    /// an annotated disassembly of `memory`, each line prefixed with how
+    /// often it was executed, read and written. Instructions that never ran
+    /// are marked `-`, and untouched data is left blank. Data with a run
+    /// count is code the program wrote for itself, since it doesn't decode
+    /// as an instruction in `memory`.
+    pub fn annotate(&self, memory: &[i128]) -> String {
+        let blank_or = |n: u64| if n == 0 { String::new() } else { n.to_string() };
+        let mut text = format!("{:>8} {:>6} {:>6}\n", "runs", "reads", "writes");
+        for line in self.listing(memory) {
+            let runs = match line.item {
+                Item::Instruction(_) => match count(&self.executed, line.address) {
+                    0 => "-".to_owned(),
+                    n => n.to_string(),
+                },
+                Item::Data(_) => blank_or(count(&self.executed, line.address)),
+            };
+            // writing to a String can't fail
+            writeln!(
+                text,
+                "{:>8} {:>6} {:>6} {}",
+                runs,
+                blank_or(count(&self.read, line.address)),
+                blank_or(count(&self.written, line.address)),
+                line
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    /// the fraction of the instructions in the `listing` of `memory` that
+    /// were executed
+    pub fn instruction_coverage(&self, memory: &[i128]) -> f64 {
+        let listing = self.listing(memory);
+        let instructions = listing
+            .iter()
+            .filter(|line| matches!(line.item, Item::Instruction(_)))
+            .map(|line| line.address)
+            .collect::<Vec<_>>();
+        if instructions.is_empty() {
+            return 0.0;
+        }
+        let executed = instructions
+            .iter()
+            .filter(|address| self.executed.contains_key(address))
+            .count();
+        executed as f64 / instructions.len() as f64
+    }
+
+    /// write the counts to `out` as a header line followed by one line per
+    /// address that was touched, e.g. `12 3 0 1` for address 12 executed
+    /// three times, never read and written once
+    pub fn save(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        let mut addresses = self
+            .executed
+            .keys()
+            .chain(self.read.keys())
+            .chain(self.written.keys())
+            .collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        for &address in addresses {
+            writeln!(
+                out,
+                "{} {} {} {}",
+                address,
+                count(&self.executed, address),
+                count(&self.read, address),
+                count(&self.written, address)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine {
+    /// run until the program halts or waits for input, adding what it
+    /// executes, reads and writes to `coverage`
+    pub fn run_covered(&mut self, coverage: &mut Coverage) -> Result<Status, MachineError> {
+        loop {
+            match self.step()? {
+                StepOutcome::Executed(step) => coverage.record(&step),
+                StepOutcome::Waiting => return Ok(Status::Waiting),
+                StepOutcome::Halted => {
+                    // reaching the halt counts as executing it
+                    *coverage.executed.entry(self.mem_ptr).or_insert(0) += 1;
+                    return Ok(Status::Halted);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // outputs 1 if its input is 8, and 0 otherwise, through two branches
+    const IS_EIGHT: &str = "3,15,1008,15,8,16,1005,16,12,104,0,99,104,1,99,0,0";
+
+    fn covered(inputs: &[i128]) -> Coverage {
+        let mut coverage = Coverage::new();
+        for &input in inputs {
+            let mut machine = Machine::new(IS_EIGHT, vec![input]);
+            machine.run_covered(&mut coverage).unwrap();
+        }
+        coverage
+    }
+
+    #[test]
+    fn test_counts() {
+        let coverage = covered(&[8]);
+        let executed = coverage.executed.keys().cloned().collect::<Vec<_>>();
+        assert!(executed == vec![0, 2, 6, 12, 14]);
+        assert!(coverage.read == vec![(15, 1), (16, 1)].into_iter().collect());
+        assert!(coverage.written == vec![(15, 1), (16, 1)].into_iter().collect());
+
+        let mut both = covered(&[1]);
+        both.merge(&coverage);
+        let executed = both.executed.keys().cloned().collect::<Vec<_>>();
+        assert!(executed == vec![0, 2, 6, 9, 11, 12, 14]);
+        assert!(both.executed[&0] == 2 && both.read[&15] == 2);
+        assert!(both == covered(&[8, 1]));
+    }
+
+    #[test]
+    fn test_annotate() {
+        let memory = Machine::new(IS_EIGHT, vec![]).memory;
+        let coverage = covered(&[8]);
+        let text = coverage.annotate(&memory);
+        let lines = text.lines().collect::<Vec<_>>();
+        let line = |counts: [&str; 3], address, text, words| {
+            format!(
+                "{:>8} {:>6} {:>6} {:>6}  {:<32} ; {}",
+                counts[0], counts[1], counts[2], address, text, words
+            )
+        };
+        assert!(lines.len() == 10);
+        assert!(lines[0] == "    runs  reads writes");
+        assert!(lines[1] == line(["1", "", ""], 0, "IN 15", "3,15"));
+        assert!(lines[4] == line(["-", "", ""], 9, "OUT #0", "104,0"));
+        assert!(lines[8] == line(["", "1", "1"], 15, ".data 0", "0"));
+        assert!((coverage.instruction_coverage(&memory) - 5.0 / 7.0).abs() < 1e-9);
+        assert!(covered(&[8, 1]).instruction_coverage(&memory) == 1.0);
+    }
+
+    #[test]
+    fn test_listing_follows_execution() {
+        // jumps over a word which a linear sweep would decode as an ADD,
+        // swallowing the HLT
+        let src = "1105,1,4,1,99,0,0,0";
+        let mut machine = Machine::new(src, vec![]);
+        let memory = machine.memory.clone();
+        let mut coverage = Coverage::new();
+        machine.run_covered(&mut coverage).unwrap();
+        let items = coverage
+            .listing(&memory)
+            .into_iter()
+            .map(|line| line.item)
+            .collect::<Vec<_>>();
+        assert!(items.len() == 6 && items[1] == Item::Data(1));
+        assert!(items[2] == Item::Instruction(Instruction::Halt));
+    }
+
+    #[test]
+    fn test_self_modified_code() {
+        // turns the word at 4 into a halt before running it
+        let src = "1101,90,9,4,0";
+        let mut machine = Machine::new(src, vec![]);
+        let memory = machine.memory.clone();
+        let mut coverage = Coverage::new();
+        assert!(machine.run_covered(&mut coverage).unwrap() == Status::Halted);
+        let text = coverage.annotate(&memory);
+        assert!(text
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("       1             1      4  .data 0 "));
+    }
+
+    #[test]
+    fn test_save() {
+        let mut out = vec![];
+        covered(&[1]).save(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(
+            text == concat!(
+                "intcode-coverage 1\n",
+                "0 1 0 0\n2 1 0 0\n6 1 0 0\n9 1 0 0\n11 1 0 0\n",
+                "15 0 1 1\n16 0 1 1\n"
+            )
+        );
+    }
+}
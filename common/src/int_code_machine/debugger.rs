@@ -0,0 +1,233 @@
+use super::{Instruction, Machine, MachineError, Step, StepOutcome};
+use std::collections::{HashMap, HashSet};
+
+/// Which kinds of access to a memory cell should stop execution
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn includes(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Watch::ReadWrite, _) | (Watch::Read, Access::Read) | (Watch::Write, Access::Write)
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Why the debugger handed control back
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// a single `step` completed without hitting anything
+    Stepped(Step),
+    /// execution reached a breakpoint; the instruction there has not run yet
+    Breakpoint(usize),
+    /// the instruction in `step` touched a watched memory cell
+    Watchpoint {
+        address: usize,
+        access: Access,
+        step: Step,
+    },
+    /// the `run_until` predicate held after `step`
+    Predicate(Step),
+    Waiting,
+    Halted,
+}
+
+/// A `Machine` wrapper with breakpoints and memory watchpoints
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: HashSet<usize>,
+    watchpoints: HashMap<usize, Watch>,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Debugger {
+            machine,
+            breakpoints: HashSet::new(),
+            watchpoints: HashMap::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// mutable access to the machine, e.g. to provide more input
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    pub fn mem_ptr(&self) -> usize {
+        self.machine.mem_ptr()
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.machine.relative_base()
+    }
+
+    pub fn current_instruction(&self) -> Result<Instruction, MachineError> {
+        self.machine.current_instruction()
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize, watch: Watch) {
+        self.watchpoints.insert(address, watch);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    /// execute a single instruction, reporting any watchpoint it triggers.
+    /// Breakpoints don't stop a single step.
+    pub fn step(&mut self) -> Result<Stop, MachineError> {
+        let step = match self.machine.step()? {
+            StepOutcome::Executed(step) => step,
+            StepOutcome::Waiting => return Ok(Stop::Waiting),
+            StepOutcome::Halted => return Ok(Stop::Halted),
+        };
+        Ok(self.check_watchpoints(step).unwrap_or(Stop::Stepped(step)))
+    }
+
+    /// run until a breakpoint or watchpoint is hit, or the machine halts or
+    /// waits for input
+    pub fn resume(&mut self) -> Result<Stop, MachineError> {
+        self.run_until(|_| false)
+    }
+
+    /// run until `predicate` holds for the machine after an instruction, or
+    /// until anything that would stop `resume`. The instruction at the current
+    /// address is always executed, so resuming from a breakpoint makes progress.
+    pub fn run_until(
+        &mut self,
+        mut predicate: impl FnMut(&Machine) -> bool,
+    ) -> Result<Stop, MachineError> {
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.machine.mem_ptr()) {
+                return Ok(Stop::Breakpoint(self.machine.mem_ptr()));
+            }
+            first = false;
+            match self.step()? {
+                Stop::Stepped(step) => {
+                    if predicate(&self.machine) {
+                        return Ok(Stop::Predicate(step));
+                    }
+                }
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    fn check_watchpoints(&self, step: Step) -> Option<Stop> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        let reads = step.reads().map(|read| (read.address, Access::Read));
+        let write = step.write.map(|write| (write.address, Access::Write));
+        reads
+            .chain(write)
+            .find(|(address, access)| {
+                self.watchpoints
+                    .get(address)
+                    .is_some_and(|watch| watch.includes(*access))
+            })
+            .map(|(address, access)| Stop::Watchpoint {
+                address,
+                access,
+                step,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::assembler::assemble;
+
+    fn countdown() -> Debugger {
+        let src = "
+            loop:   OUT count
+                    ADD count, #-1, count
+                    JT count, #loop
+                    HLT
+            count:  .data 3
+        ";
+        Debugger::new(Machine::new(&assemble(src).unwrap(), vec![]))
+    }
+
+    #[test]
+    fn test_step() {
+        let mut debugger = countdown();
+        assert!(debugger.current_instruction().unwrap().mnemonic() == "OUT");
+        match debugger.step().unwrap() {
+            Stop::Stepped(step) => {
+                assert!(step.output == Some(3));
+                assert!(step.reads().next().unwrap().address == 10);
+            }
+            stop => panic!("unexpected stop {:?}", stop),
+        }
+        assert!(debugger.mem_ptr() == 2);
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut debugger = countdown();
+        debugger.add_breakpoint(0);
+        assert!(debugger.resume().unwrap() == Stop::Breakpoint(0));
+        assert!(debugger.machine().output == vec![3]);
+        assert!(debugger.resume().unwrap() == Stop::Breakpoint(0));
+        assert!(debugger.machine().output == vec![3, 2]);
+        debugger.remove_breakpoint(0);
+        assert!(debugger.resume().unwrap() == Stop::Halted);
+        assert!(debugger.machine().output == vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut debugger = countdown();
+        debugger.add_watchpoint(10, Watch::Write);
+        match debugger.resume().unwrap() {
+            Stop::Watchpoint {
+                address,
+                access,
+                step,
+            } => {
+                assert!(address == 10 && access == Access::Write);
+                assert!(step.address == 2);
+                assert!(step.write.unwrap().new == 2);
+            }
+            stop => panic!("unexpected stop {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut debugger = countdown();
+        let stop = debugger.run_until(|m| m.output.len() == 2).unwrap();
+        assert!(matches!(stop, Stop::Predicate(step) if step.output == Some(2)));
+        assert!(debugger.relative_base() == 0);
+    }
+}
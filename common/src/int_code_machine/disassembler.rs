@@ -0,0 +1,140 @@
+use super::Instruction;
+use std::fmt;
+
+/// What a word (or run of words) in memory was decoded as
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Instruction(Instruction),
+    /// a word that doesn't decode to a complete instruction
+    Data(i128),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingLine {
+    pub address: usize,
+    pub item: Item,
+    /// the raw memory words covered by this line
+    pub words: Vec<i128>,
+}
+
+impl fmt::Display for ListingLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match &self.item {
+            Item::Instruction(instruction) => instruction.to_string(),
+            Item::Data(value) => format!(".data {}", value),
+        };
+        let words = self
+            .words
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        write!(f, "{:>6}  {:<32} ; {}", self.address, text, words)
+    }
+}
+
+/// A disassembled program, one line per instruction or data word
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    /// the line starting at `address`, if any
+    pub fn line_at(&self, address: usize) -> Option<&ListingLine> {
+        self.lines
+            .binary_search_by_key(&address, |line| line.address)
+            .ok()
+            .map(|i| &self.lines[i])
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble `memory` with a linear sweep from address 0.
+///
+/// Every word that starts a complete, valid instruction is decoded as one,
+/// and everything else is shown as data. Since Intcode doesn't distinguish
+/// code from data, data that happens to look like an instruction will be
+/// listed as one, and can push the following instructions out of alignment.
+pub fn disassemble(memory: &[i128]) -> Listing {
+    let mut lines = vec![];
+    let mut address = 0;
+    while address < memory.len() {
+        let item = match Instruction::decode(&memory[address..], address) {
+            Ok(instruction) if address + instruction.size() <= memory.len() => {
+                Item::Instruction(instruction)
+            }
+            _ => Item::Data(memory[address]),
+        };
+        let size = match &item {
+            Item::Instruction(instruction) => instruction.size(),
+            Item::Data(_) => 1,
+        };
+        lines.push(ListingLine {
+            address,
+            item,
+            words: memory[address..address + size].to_vec(),
+        });
+        address += size;
+    }
+    Listing { lines }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::Machine;
+
+    #[test]
+    fn test_disassemble_modes() {
+        let machine = Machine::new("109,19,21101,3,4,-2,204,-2,99", vec![]);
+        let listing = machine.disassemble();
+        let text = listing
+            .lines
+            .iter()
+            .map(|line| match &line.item {
+                Item::Instruction(i) => i.to_string(),
+                Item::Data(d) => format!(".data {}", d),
+            })
+            .collect::<Vec<_>>();
+        assert!(text == vec!["ARB #19", "ADD #3, #4, @-2", "OUT @-2", "HLT"]);
+        assert!(listing.line_at(6).unwrap().words == vec![204, -2]);
+        assert!(listing.line_at(7).is_none());
+    }
+
+    #[test]
+    fn test_disassemble_data() {
+        let listing = disassemble(&[1105, 1, 4, 77, 99, 1, 0]);
+        let items = listing.lines.iter().map(|l| l.item).collect::<Vec<_>>();
+        assert!(
+            items
+                == vec![
+                    Item::Instruction(Instruction::decode(&[1105, 1, 4], 0).unwrap()),
+                    Item::Data(77),
+                    Item::Instruction(Instruction::Halt),
+                    Item::Data(1),
+                    Item::Data(0),
+                ]
+        );
+    }
+
+    #[test]
+    fn test_listing_format() {
+        let listing = disassemble(&[1002, 4, 3, 4, 33]);
+        assert!(
+            listing.to_string()
+                == format!(
+                    "{:>6}  {:<32} ; 1002,4,3,4\n{:>6}  {:<32} ; 33\n",
+                    0, "MUL 4, #3, 4", 4, ".data 33"
+                )
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use super::{Machine, Memory, Step, Word};
+use std::collections::VecDeque;
+
+/// whether an instruction's input was read from the machine's own `input`
+/// buffer, so that stepping back should unread it, and whether its output
+/// was kept in the `output` buffer, so that stepping back should remove it
+#[derive(Copy, Clone)]
+pub(super) struct Recording {
+    pub(super) input_kept: bool,
+    pub(super) output_kept: bool,
+}
+
+impl Recording {
+    pub(super) const BUFFERED: Recording = Recording {
+        input_kept: true,
+        output_kept: true,
+    };
+    /// I/O through other sources and sinks, which can't be taken back
+    pub(super) const EXTERNAL: Recording = Recording {
+        input_kept: false,
+        output_kept: false,
+    };
+}
+
+/// what it takes to undo one instruction
+#[derive(Clone, Debug)]
+struct Undo<W> {
+    mem_ptr: usize,
+    /// the address written, and its value beforehand
+    write: Option<(usize, W)>,
+    relative_base: Option<isize>,
+    input: bool,
+    output: bool,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct History<W> {
+    undo: VecDeque<Undo<W>>,
+    limit: usize,
+}
+
+impl<W: Word, M: Memory<Word = W>> Machine<M> {
+    /// start keeping an undo log of the last `limit` instructions, so they
+    /// can be taken back with `step_back`. Anything already recorded is
+    /// forgotten.
+    ///
+    /// Every instruction executed is recorded, however the machine is run.
+    /// I/O through the sources and sinks given to `run_with` and `step_with`
+    /// can't be taken back, though: stepping back over an input doesn't
+    /// return the value to its source, and output already sent stays sent.
+    pub fn record_history(&mut self, limit: usize) {
+        self.history = Some(History {
+            undo: VecDeque::new(),
+            limit,
+        });
+    }
+
+    /// stop recording and forget the history
+    pub fn stop_recording_history(&mut self) {
+        self.history = None;
+    }
+
+    /// how many instructions `step_back` can currently undo
+    pub fn history_len(&self) -> usize {
+        self.history
+            .as_ref()
+            .map_or(0, |history| history.undo.len())
+    }
+
+    pub(super) fn remember(&mut self, step: &Step<W>, recording: Recording) {
+        let history = match &mut self.history {
+            Some(history) => history,
+            None => return,
+        };
+        if history.limit == 0 {
+            return;
+        }
+        if history.undo.len() == history.limit {
+            history.undo.pop_front();
+        }
+        history.undo.push_back(Undo {
+            mem_ptr: step.address,
+            write: step
+                .write
+                .as_ref()
+                .map(|write| (write.address, write.old.clone())),
+            relative_base: step.relative_base.map(|(old, _)| old),
+            input: recording.input_kept && step.input.is_some(),
+            output: recording.output_kept && step.output.is_some(),
+        });
+    }
+
+    /// undo the last recorded instruction, returning false if there's
+    /// nothing left to undo. Input it read from `input` is unread, so it will
+    /// be read again, and output it produced is removed from `output` if it's
+    /// still there.
+    pub fn step_back(&mut self) -> bool {
+        let undo = match self.history.as_mut().and_then(|h| h.undo.pop_back()) {
+            Some(undo) => undo,
+            None => return false,
+        };
+        if let Some((address, old)) = undo.write {
+            self.memory.write(address, old);
+        }
+        if let Some(relative_base) = undo.relative_base {
+            self.relative_base = relative_base;
+        }
+        if undo.input {
+            self.input_ptr -= 1;
+        }
+        if undo.output {
+            self.output.pop();
+        }
+        self.mem_ptr = undo.mem_ptr;
+        true
+    }
+
+    /// step back until `predicate` holds for the machine, returning false if
+    /// the history ran out first
+    pub fn run_back_until(&mut self, mut predicate: impl FnMut(&Self) -> bool) -> bool {
+        while self.step_back() {
+            if predicate(self) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::{Instruction, Status};
+    use std::collections::VecDeque;
+
+    // twice reads two values onto a stack and outputs their sum
+    const SUMS: &str = "109,25,203,0,203,1,22201,0,1,2,204,2,\
+                        109,3,203,0,203,1,22201,0,1,2,204,2,99,0,0,0,0,0,0";
+
+    #[test]
+    fn test_step_back() {
+        let mut machine = Machine::new(SUMS, vec![1, 2, 3, 4]);
+        machine.record_history(100);
+        let start = machine.clone();
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![3, 7]);
+        let executed = machine.history_len();
+        assert!(executed == 10);
+
+        assert!(machine.step_back());
+        assert!(machine.mem_ptr() == 22 && machine.output == vec![3]);
+        assert!(machine.step_back());
+        assert!(machine.mem_ptr() == 18 && machine.relative_base() == 28);
+
+        while machine.step_back() {}
+        assert!(machine.memory == start.memory && machine.mem_ptr() == 0);
+        assert!(machine.relative_base() == 0 && machine.input_ptr() == 0);
+        assert!(machine.output.is_empty() && machine.history_len() == 0);
+
+        // replaying gives the same result
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![3, 7] && machine.history_len() == executed);
+    }
+
+    #[test]
+    fn test_run_back_until() {
+        let mut machine = Machine::new(SUMS, vec![1, 2, 3, 4]);
+        machine.record_history(100);
+        machine.run();
+        // rewind to the last input read, and change it
+        let is_input = |m: &Machine| matches!(m.current_instruction(), Ok(Instruction::Input(_)));
+        assert!(machine.run_back_until(is_input));
+        assert!(machine.input_ptr() == 3 && machine.output == vec![3]);
+        machine.input[3] = 10;
+        machine.run();
+        assert!(machine.output == vec![3, 13]);
+
+        machine.stop_recording_history();
+        assert!(!machine.run_back_until(|_| true));
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut machine = Machine::new(SUMS, vec![1, 2, 3, 4]);
+        machine.wait_on_input();
+        machine.record_history(3);
+        assert!(machine.run_to_output() == Status::Output(3));
+        assert!(machine.history_len() == 3);
+        // the output was returned, not kept, so stepping back leaves it be
+        machine.output.push(99);
+        assert!(!machine.run_back_until(|_| false));
+        assert!(machine.mem_ptr() == 4 && machine.output == vec![99]);
+
+        // stepping is recorded too
+        machine.step().unwrap();
+        assert!(machine.history_len() == 1 && machine.input_ptr() == 2);
+        assert!(machine.step_back() && machine.input_ptr() == 1);
+    }
+
+    #[test]
+    fn test_external_io() {
+        // the first two values come from the machine's own input, the rest
+        // from elsewhere
+        let mut machine = Machine::new(SUMS, vec![1, 2]);
+        machine.wait_on_input();
+        machine.record_history(100);
+        assert!(machine.run() == Status::Waiting);
+        let mut sent = vec![];
+        let mut elsewhere = VecDeque::from(vec![3, 4]);
+        let status = machine.run_with(&mut elsewhere, &mut sent).unwrap();
+        assert!(status == Status::Halted && sent == vec![7]);
+        assert!(machine.history_len() == 10);
+
+        // output sent elsewhere stays sent, and input read from elsewhere
+        // isn't taken back from `input`
+        assert!(machine.step_back() && machine.mem_ptr() == 22);
+        assert!(machine.output == vec![3] && sent == vec![7]);
+        assert!(machine.step_back() && machine.mem_ptr() == 18 && machine.memory[30] == 0);
+        assert!(machine.step_back() && machine.memory[29] == 0);
+        assert!(machine.input_ptr() == 2);
+        assert!(machine.step_back() && machine.step_back());
+        assert!(machine.mem_ptr() == 12 && machine.relative_base() == 25);
+
+        // further back, the buffered I/O is undone as usual
+        assert!(machine.step_back() && machine.output.is_empty());
+        let mut elsewhere = VecDeque::from(vec![5, 6]);
+        assert!(machine.run_with(&mut elsewhere, &mut sent).unwrap() == Status::Halted);
+        assert!(sent == vec![7, 3, 11]);
+    }
+}
@@ -0,0 +1,210 @@
+use super::{Machine, Memory, Word};
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, Mutex};
+
+type ReadHook<W> = dyn FnMut(usize) -> W + Send;
+type WriteHook<W> = dyn FnMut(usize, &W) + Send;
+
+/// A hook and the (inclusive) range of addresses it's mapped to. The hook
+/// is shared, so clones of a machine drive the same peripheral.
+struct Mapped<H: ?Sized> {
+    first: usize,
+    last: usize,
+    hook: Arc<Mutex<H>>,
+}
+
+impl<H: ?Sized> Clone for Mapped<H> {
+    fn clone(&self) -> Self {
+        Mapped {
+            first: self.first,
+            last: self.last,
+            hook: Arc::clone(&self.hook),
+        }
+    }
+}
+
+/// the first and last address in `addresses`, or `None` if it's empty
+pub(super) fn inclusive_bounds(addresses: impl RangeBounds<usize>) -> Option<(usize, usize)> {
+    let first = match addresses.start_bound() {
+        Bound::Included(&first) => first,
+        Bound::Excluded(&first) => first.checked_add(1)?,
+        Bound::Unbounded => 0,
+    };
+    let last = match addresses.end_bound() {
+        Bound::Included(&last) => last,
+        Bound::Excluded(&end) => end.checked_sub(1)?,
+        Bound::Unbounded => usize::MAX,
+    };
+    if last < first {
+        return None;
+    }
+    Some((first, last))
+}
+
+impl<H: ?Sized> Mapped<H> {
+    /// `None` if `addresses` is empty
+    fn new(addresses: impl RangeBounds<usize>, hook: Arc<Mutex<H>>) -> Option<Self> {
+        let (first, last) = inclusive_bounds(addresses)?;
+        Some(Mapped { first, last, hook })
+    }
+
+    fn covers(&self, address: usize) -> bool {
+        self.first <= address && address <= self.last
+    }
+}
+
+/// The read and write hooks registered on a machine
+pub(super) struct Hooks<W> {
+    reads: Vec<Mapped<ReadHook<W>>>,
+    writes: Vec<Mapped<WriteHook<W>>>,
+}
+
+impl<W> Default for Hooks<W> {
+    fn default() -> Self {
+        Hooks {
+            reads: vec![],
+            writes: vec![],
+        }
+    }
+}
+
+impl<W> Clone for Hooks<W> {
+    fn clone(&self) -> Self {
+        Hooks {
+            reads: self.reads.clone(),
+            writes: self.writes.clone(),
+        }
+    }
+}
+
+impl<W> Hooks<W> {
+    pub(super) fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    /// whether any read hook covers an address in `address..address + len`
+    pub(super) fn maps_reads(&self, address: usize, len: usize) -> bool {
+        !self.reads.is_empty()
+            && self
+                .reads
+                .iter()
+                .any(|mapped| (address..address.saturating_add(len)).any(|a| mapped.covers(a)))
+    }
+
+    /// the value the hook mapped to `address` gives, if there is one. The
+    /// most recently registered hook wins.
+    pub(super) fn read(&self, address: usize) -> Option<W> {
+        if self.reads.is_empty() {
+            return None;
+        }
+        let mapped = self.reads.iter().rev().find(|m| m.covers(address))?;
+        let mut hook = mapped.hook.lock().unwrap();
+        Some(hook(address))
+    }
+
+    /// tell every write hook mapped to `address` about `value`
+    pub(super) fn write(&self, address: usize, value: &W) {
+        for mapped in self.writes.iter().filter(|m| m.covers(address)) {
+            let mut hook = mapped.hook.lock().unwrap();
+            hook(address, value);
+        }
+    }
+}
+
+impl<W: Word, M: Memory<Word = W>> Machine<M> {
+    /// map `addresses` to `hook`: whenever an instruction reads one of them,
+    /// including when fetching an instruction from there, the value comes
+    /// from `hook` instead of memory. If hooks overlap, the one registered
+    /// last is used.
+    ///
+    /// Peeking at memory, e.g. with `current_instruction`, doesn't call
+    /// hooks. Cloning the machine shares its hooks.
+    pub fn on_read(
+        &mut self,
+        addresses: impl RangeBounds<usize>,
+        hook: impl FnMut(usize) -> W + Send + 'static,
+    ) {
+        let hook: Arc<Mutex<ReadHook<W>>> = Arc::new(Mutex::new(hook));
+        self.hooks.reads.extend(Mapped::new(addresses, hook));
+    }
+
+    /// call `hook` with the address and value whenever an instruction writes
+    /// to one of `addresses`. The value is still stored in memory.
+    pub fn on_write(
+        &mut self,
+        addresses: impl RangeBounds<usize>,
+        hook: impl FnMut(usize, &W) + Send + 'static,
+    ) {
+        let hook: Arc<Mutex<WriteHook<W>>> = Arc::new(Mutex::new(hook));
+        self.hooks.writes.extend(Mapped::new(addresses, hook));
+    }
+
+    /// remove every read and write hook
+    pub fn clear_hooks(&mut self) {
+        self.hooks = Hooks::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::int_code_machine::{Machine, Status};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_read_hook() {
+        // polls a status register at 100 until it's non-zero, then outputs
+        // the data register at 101
+        let mut machine = Machine::new("1006,100,0,4,101,99", vec![]);
+        let mut polls = 0;
+        machine.on_read(100..=100, move |_| {
+            polls += 1;
+            (polls == 3) as i128
+        });
+        machine.on_read(101.., |address| address as i128 * 2);
+        machine.on_read(0..0, |_| 99);
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![202]);
+        // reads are only hooked, memory is untouched
+        assert!(machine.memory.len() == 6);
+    }
+
+    #[test]
+    fn test_fetch_hook() {
+        // a coin slot at address 0: the program adds when it reads 1 there,
+        // and multiplies when it reads 2
+        let src = "1,5,6,7,99,3,4,0";
+        let mut machine = Machine::new(src, vec![]);
+        machine.run();
+        assert!(machine.memory[7] == 7);
+
+        let mut machine = Machine::new(src, vec![]);
+        machine.on_read(0..1, |_| 2);
+        machine.run();
+        assert!(machine.memory[7] == 12 && machine.memory[0] == 1);
+
+        // later hooks take priority
+        let mut machine = Machine::new(src, vec![]);
+        machine.on_read(0..1, |_| 2);
+        machine.on_read(0..=0, |_| 99);
+        assert!(machine.run() == Status::Halted && machine.memory[7] == 0);
+    }
+
+    #[test]
+    fn test_write_hook() {
+        // writes 1 then 2 to a display register at 20, and 3 to 21
+        let mut machine = Machine::new("1101,0,1,20,1101,0,2,20,1101,0,3,21,99", vec![]);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let display = Arc::clone(&seen);
+        machine.on_write(20..21, move |address, &value| {
+            display.lock().unwrap().push((address, value))
+        });
+        let mut unhooked = machine.clone();
+        unhooked.clear_hooks();
+        assert!(unhooked.run() == Status::Halted);
+        assert!(seen.lock().unwrap().is_empty());
+
+        machine.run();
+        assert!(*seen.lock().unwrap() == vec![(20, 1), (20, 2)]);
+        assert!(machine.memory[20] == 2 && machine.memory[21] == 3);
+    }
+}
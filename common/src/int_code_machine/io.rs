@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+
+/// Somewhere a `Machine` can take its input from. A queue of values should
+/// be a `VecDeque`; there's deliberately no impl for `Vec`, which can't give
+/// up its front value without shifting the rest.
+pub trait InputSource<W = i128> {
+    /// the next input value, or `None` if there isn't one (yet)
+    fn next_input(&mut self) -> Option<W>;
+}
+
+/// Somewhere a `Machine` can send its output to
+pub trait OutputSink<W = i128> {
+    fn send_output(&mut self, value: W);
+}
+
+impl<W> OutputSink<W> for Vec<W> {
+    fn send_output(&mut self, value: W) {
+        self.push(value)
+    }
+}
+
+impl<W> InputSource<W> for VecDeque<W> {
+    fn next_input(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W> OutputSink<W> for VecDeque<W> {
+    fn send_output(&mut self, value: W) {
+        self.push_back(value)
+    }
+}
+
+impl<W, F> InputSource<W> for F
+where
+    F: FnMut() -> Option<W>,
+{
+    fn next_input(&mut self) -> Option<W> {
+        self()
+    }
+}
+
+impl<W, F> OutputSink<W> for F
+where
+    F: FnMut(W),
+{
+    fn send_output(&mut self, value: W) {
+        self(value)
+    }
+}
+
+/// Blocks until a value arrives, and runs dry once every sender is gone
+impl<W> InputSource<W> for Receiver<W> {
+    fn next_input(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+/// Output sent after the receiver has hung up is dropped
+impl<W> OutputSink<W> for Sender<W> {
+    fn send_output(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+/// Output sent after the receiver has hung up is dropped
+impl<W> OutputSink<W> for SyncSender<W> {
+    fn send_output(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+/// The machine's own `input` buffer, read from `ptr` onwards
+pub(super) struct BufferedInput<W> {
+    pub(super) values: Vec<W>,
+    pub(super) ptr: usize,
+}
+
+impl<W: Clone> InputSource<W> for BufferedInput<W> {
+    fn next_input(&mut self) -> Option<W> {
+        let value = self.values.get(self.ptr).cloned()?;
+        self.ptr += 1;
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::{Machine, Status};
+    use std::cell::Cell;
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    // reads numbers until it reads 0, printing each one doubled
+    fn doubler() -> Machine {
+        Machine::new("3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0", vec![])
+    }
+
+    #[test]
+    fn test_collections() {
+        let mut machine = doubler();
+        let mut input = VecDeque::from(vec![1, 2, 3, 0]);
+        let mut output = vec![];
+        assert!(machine.run_with(&mut input, &mut output).unwrap() == Status::Halted);
+        assert!(output == vec![2, 4, 6]);
+        assert!(machine.output.is_empty());
+
+        let mut machine = doubler();
+        let mut output = VecDeque::new();
+        machine
+            .run_with(&mut VecDeque::from(vec![5, 0]), &mut output)
+            .unwrap();
+        assert!(output == vec![10]);
+    }
+
+    #[test]
+    fn test_closures() {
+        // each input is the last output plus one, like a controller reacting
+        // to what the program just did
+        let last = Cell::new(0);
+        let mut input = || {
+            let next = last.get() + 1;
+            if next > 20 {
+                Some(0)
+            } else {
+                Some(next)
+            }
+        };
+        let mut seen = vec![];
+        let mut output = |value| {
+            last.set(value);
+            seen.push(value);
+        };
+        doubler().run_with(&mut input, &mut output).unwrap();
+        assert!(seen == vec![2, 6, 14, 30]);
+    }
+
+    #[test]
+    fn test_channels() {
+        let (input_tx, mut input_rx) = channel();
+        let (mut output_tx, output_rx) = channel();
+        let handle = thread::spawn(move || {
+            let mut machine = doubler();
+            machine.run_with(&mut input_rx, &mut output_tx).unwrap()
+        });
+        for i in 1..=3 {
+            input_tx.send(i).unwrap();
+            assert!(output_rx.recv().unwrap() == i * 2);
+        }
+        input_tx.send(0).unwrap();
+        assert!(handle.join().unwrap() == Status::Halted);
+    }
+
+    #[test]
+    fn test_empty_source() {
+        let mut machine = doubler();
+        let mut input = VecDeque::from(vec![4]);
+        assert!(machine.run_with(&mut input, &mut vec![]).is_err());
+
+        let mut machine = doubler();
+        machine.wait_on_input();
+        assert!(machine.run_with(&mut input, &mut vec![]).unwrap() == Status::Waiting);
+    }
+}
@@ -0,0 +1,191 @@
+use super::Word;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+/// Storage for a `Machine`'s memory. Every address is valid, and cells that
+/// have never been written read as 0.
+pub trait Memory: Clone {
+    /// the type of value stored in each cell, which is also the type the
+    /// machine computes with
+    type Word: Word;
+
+    /// memory holding `words` from address 0
+    fn from_words(words: Vec<Self::Word>) -> Self;
+
+    fn read(&self, address: usize) -> Self::Word;
+
+    fn write(&mut self, address: usize, value: Self::Word);
+
+    /// fill `buf` with the words from `address` onwards
+    fn read_into(&self, address: usize, buf: &mut [Self::Word]) {
+        for (i, word) in buf.iter_mut().enumerate() {
+            *word = self.read(address.saturating_add(i));
+        }
+    }
+}
+
+/// The dense backend: a write past the end grows the vector to at least
+/// twice its size, so memory use follows the highest address written
+impl<W: Word> Memory for Vec<W> {
+    type Word = W;
+
+    fn from_words(words: Vec<W>) -> Self {
+        words
+    }
+
+    fn read(&self, address: usize) -> W {
+        self.get(address).cloned().unwrap_or_else(W::zero)
+    }
+
+    fn write(&mut self, address: usize, value: W) {
+        if address >= self.len() {
+            let target_size = max(2 * self.len(), address);
+            self.resize(target_size + 1, W::zero());
+        }
+        self[address] = value;
+    }
+
+    fn read_into(&self, address: usize, buf: &mut [W]) {
+        let available = self.get(address..).unwrap_or(&[]);
+        let n = min(available.len(), buf.len());
+        buf[..n].clone_from_slice(&available[..n]);
+        buf[n..].fill(W::zero());
+    }
+}
+
+const PAGE_SIZE: usize = 1024;
+
+/// The sparse backend: memory is split into pages which are only allocated
+/// when first written, so a program writing to a huge address costs one page
+/// rather than everything below it. Reads never allocate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PagedMemory<W = i128> {
+    pages: HashMap<usize, Box<[W]>>,
+    /// what unallocated cells read as, kept here so `Index` can borrow it
+    zero: W,
+}
+
+impl<W: Word> Default for PagedMemory<W> {
+    fn default() -> Self {
+        PagedMemory {
+            pages: HashMap::new(),
+            zero: W::zero(),
+        }
+    }
+}
+
+impl<W: Word> PagedMemory<W> {
+    pub fn new() -> Self {
+        PagedMemory::default()
+    }
+
+    /// the number of pages allocated so far
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn page(&self, address: usize) -> Option<&[W]> {
+        self.pages.get(&(address / PAGE_SIZE)).map(|page| &page[..])
+    }
+}
+
+impl<W: Word> Memory for PagedMemory<W> {
+    type Word = W;
+
+    fn from_words(words: Vec<W>) -> Self {
+        let mut memory = PagedMemory::new();
+        for (address, word) in words.into_iter().enumerate() {
+            memory.write(address, word);
+        }
+        memory
+    }
+
+    fn read(&self, address: usize) -> W {
+        self[address].clone()
+    }
+
+    fn write(&mut self, address: usize, value: W) {
+        self[address] = value;
+    }
+
+    fn read_into(&self, address: usize, buf: &mut [W]) {
+        let offset = address % PAGE_SIZE;
+        if offset + buf.len() > PAGE_SIZE {
+            // straddles two pages
+            for (i, word) in buf.iter_mut().enumerate() {
+                *word = self.read(address.saturating_add(i));
+            }
+            return;
+        }
+        match self.page(address) {
+            Some(page) => buf.clone_from_slice(&page[offset..offset + buf.len()]),
+            None => buf.fill(W::zero()),
+        }
+    }
+}
+
+impl<W: Word> Index<usize> for PagedMemory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        self.page(address)
+            .map_or(&self.zero, |page| &page[address % PAGE_SIZE])
+    }
+}
+
+/// Allocates the page holding `address` if need be
+impl<W: Word> IndexMut<usize> for PagedMemory<W> {
+    fn index_mut(&mut self, address: usize) -> &mut W {
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| vec![W::zero(); PAGE_SIZE].into_boxed_slice());
+        &mut page[address % PAGE_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::{Machine, Status};
+
+    #[test]
+    fn test_paged_memory() {
+        let mut memory = PagedMemory::<i128>::from_words(vec![1, 2, 3]);
+        assert!(memory.pages() == 1);
+        memory.write(5 * PAGE_SIZE - 1, 7);
+        memory.write(5 * PAGE_SIZE, 8);
+        assert!(memory.pages() == 3);
+        assert!(memory.read(1_000_000_000) == 0 && memory.pages() == 3);
+
+        let mut buf = [0; 3];
+        memory.read_into(5 * PAGE_SIZE - 2, &mut buf);
+        assert!(buf == [0, 7, 8]);
+        memory.read_into(1, &mut buf);
+        assert!(buf == [2, 3, 0]);
+        assert!(memory[2] == 3 && memory[PAGE_SIZE] == 0);
+    }
+
+    #[test]
+    fn test_dense_memory() {
+        let mut memory = Vec::<i128>::from_words(vec![1, 2]);
+        assert!(memory.read(10) == 0 && memory.len() == 2);
+        memory.write(10, 4);
+        assert!(memory.len() == 11 && memory[10] == 4);
+        let mut buf = [9; 3];
+        memory.read_into(9, &mut buf);
+        assert!(buf == [0, 4, 0]);
+    }
+
+    #[test]
+    fn test_huge_address() {
+        // writes its input to address 10^12, reads it back and outputs it
+        let src = "3,1000000000000,4,1000000000000,99";
+        let mut machine: Machine<PagedMemory> = Machine::with_memory(src, vec![42]);
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![42]);
+        assert!(machine.memory.pages() == 2);
+        assert!(machine.memory[1_000_000_000_000] == 42);
+    }
+}
@@ -0,0 +1,306 @@
+use super::{InputSource, Machine, MachineError, OutputSink, Status};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// how many instructions a machine runs between checks for `stop`
+const STOP_CHECK_INTERVAL: u64 = 10_000;
+/// how long a machine blocks on its input channel between checks for `stop`
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Identifies a machine within a `Network`
+pub type NodeId = usize;
+
+struct Node {
+    machine: Machine,
+    sender: Sender<i128>,
+    receiver: Receiver<i128>,
+    outputs: Vec<Sender<i128>>,
+}
+
+/// A set of machines, each run on its own thread, with outputs piped into
+/// other machines' inputs through channels.
+///
+/// A machine first consumes whatever is already in its `input` buffer (e.g.
+/// the day 7 phase settings) and then blocks on its channel. Once every
+/// machine and external sender feeding a channel is gone the channel runs dry,
+/// so a halting machine lets the machines downstream of it finish too: with
+/// `wait_on_input` they stop `Waiting`, otherwise they fail with
+/// `InputExhausted`.
+#[derive(Default)]
+pub struct Network {
+    nodes: Vec<Node>,
+}
+
+/// The state of a machine once its thread has finished
+pub struct Finished {
+    /// the machine, with every value it read appended to `input` and every
+    /// value it sent appended to `output`
+    pub machine: Machine,
+    pub result: Result<Status, MachineError>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Network::default()
+    }
+
+    pub fn add(&mut self, machine: Machine) -> NodeId {
+        let (sender, receiver) = channel();
+        self.nodes.push(Node {
+            machine,
+            sender,
+            receiver,
+            outputs: vec![],
+        });
+        self.nodes.len() - 1
+    }
+
+    /// send every output of `from` to the input of `to`. A machine's output
+    /// goes to all of its connections, and a machine can take input from
+    /// several others, in which case their values are interleaved as they arrive.
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        let sender = self.nodes[to].sender.clone();
+        self.nodes[from].outputs.push(sender);
+    }
+
+    /// a sender for feeding input to `node` from outside the network. `node`
+    /// can't run dry while this is alive.
+    pub fn input(&self, node: NodeId) -> Sender<i128> {
+        self.nodes[node].sender.clone()
+    }
+
+    /// a receiver for every value `node` outputs
+    pub fn output(&mut self, node: NodeId) -> Receiver<i128> {
+        let (sender, receiver) = channel();
+        self.nodes[node].outputs.push(sender);
+        receiver
+    }
+
+    /// start every machine on its own thread
+    pub fn spawn(self) -> RunningNetwork {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handles = self
+            .nodes
+            .into_iter()
+            .map(|node| {
+                let stop = Arc::clone(&stop);
+                thread::spawn(move || run_node(node, &stop))
+            })
+            .collect();
+        RunningNetwork { handles, stop }
+    }
+
+    /// run every machine until it stops, returning them in the order they
+    /// were added
+    pub fn run(self) -> Vec<Finished> {
+        self.spawn().join()
+    }
+}
+
+/// A `Network` whose machines are running
+pub struct RunningNetwork {
+    handles: Vec<JoinHandle<Finished>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl RunningNetwork {
+    /// wait for every machine to stop, returning them in the order they were
+    /// added. A panic on any machine's thread is propagated.
+    pub fn join(self) -> Vec<Finished> {
+        self.handles
+            .into_iter()
+            .map(|handle| match handle.join() {
+                Ok(finished) => finished,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect()
+    }
+
+    /// tell every machine to stop, then `join` them. A machine that hasn't
+    /// stopped by itself finishes with `Status::BudgetExhausted`, before an
+    /// instruction or an input it was waiting for, so it can be resumed.
+    pub fn stop(self) -> Vec<Finished> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join()
+    }
+}
+
+/// `run_with` in slices of `STOP_CHECK_INTERVAL` instructions, until the
+/// machine stops by itself or `stop` is set. The machine's own budget and
+/// deadline still apply.
+fn run_until_stopped<I, O>(
+    machine: &mut Machine,
+    input: &mut I,
+    output: &mut O,
+    stop: &AtomicBool,
+) -> Result<Status, MachineError>
+where
+    I: InputSource<i128> + ?Sized,
+    O: OutputSink<i128> + ?Sized,
+{
+    loop {
+        let budget = machine.instruction_budget();
+        let slice = budget.map_or(STOP_CHECK_INTERVAL, |b| b.min(STOP_CHECK_INTERVAL));
+        machine.set_instruction_budget(Some(slice));
+        let result = machine.run_with(input, output);
+        let spent = slice - machine.instruction_budget().unwrap_or(0);
+        machine.set_instruction_budget(budget.map(|b| b - spent));
+        match result {
+            // only the slice ran out
+            Ok(Status::BudgetExhausted)
+                if spent == slice && budget != Some(spent) && !stop.load(Ordering::Relaxed) => {}
+            result => return result,
+        }
+    }
+}
+
+fn run_node(node: Node, stop: &AtomicBool) -> Finished {
+    let Node {
+        mut machine,
+        sender,
+        receiver,
+        mut outputs,
+    } = node;
+    // only other machines and external senders should keep the channel open
+    drop(sender);
+    // whether the machine gave up waiting for input because of `stop`
+    let interrupted = Cell::new(false);
+    let result = machine.with_buffers(|machine, buffered, output| {
+        let mut input = || {
+            buffered.next_input().or_else(|| loop {
+                if stop.load(Ordering::Relaxed) {
+                    interrupted.set(true);
+                    return None;
+                }
+                match receiver.recv_timeout(STOP_POLL_INTERVAL) {
+                    Ok(value) => {
+                        buffered.values.push(value);
+                        buffered.ptr += 1;
+                        return Some(value);
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            })
+        };
+        let mut sink = |value| {
+            output.push(value);
+            for sender in outputs.iter_mut() {
+                sender.send_output(value);
+            }
+        };
+        run_until_stopped(machine, &mut input, &mut sink, stop)
+    });
+    let result = match result {
+        Ok(Status::Waiting) | Err(MachineError::InputExhausted { .. }) if interrupted.get() => {
+            Ok(Status::BudgetExhausted)
+        }
+        result => result,
+    };
+    Finished { machine, result }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FEEDBACK: &str = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,\
+        27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+
+    fn amplifiers(phases: &[i128]) -> (Network, Vec<NodeId>) {
+        let mut network = Network::new();
+        let ids = phases
+            .iter()
+            .map(|&phase| network.add(Machine::new(FEEDBACK, vec![phase])))
+            .collect::<Vec<_>>();
+        for (i, &id) in ids.iter().enumerate() {
+            network.connect(id, ids[(i + 1) % ids.len()]);
+        }
+        (network, ids)
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        let (network, ids) = amplifiers(&[9, 8, 7, 6, 5]);
+        network.input(ids[0]).send(0).unwrap();
+        let finished = network.run();
+        assert!(finished.len() == 5);
+        assert!(finished
+            .iter()
+            .all(|f| f.result.as_ref().unwrap() == &Status::Halted));
+        assert!(*finished[4].machine.output.last().unwrap() == 139629729);
+        // the first amplifier read its phase, the initial 0 and then every
+        // value from the last amplifier except the final one
+        let first = &finished[0].machine;
+        assert!(first.input[..2] == [9, 0]);
+        assert!(first.input[2..] == finished[4].machine.output[..4]);
+    }
+
+    #[test]
+    fn test_taps_and_external_input() {
+        // doubles its input until it reads 0
+        let doubler = "3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,0";
+        let mut network = Network::new();
+        let a = network.add(Machine::new(doubler, vec![]));
+        let b = network.add(Machine::new(doubler, vec![]));
+        network.connect(a, b);
+        let input = network.input(a);
+        let tap = network.output(a);
+        let out = network.output(b);
+        let running = network.spawn();
+        for i in 1..=3 {
+            input.send(i).unwrap();
+            assert!(tap.recv().unwrap() == 2 * i);
+            assert!(out.recv().unwrap() == 4 * i);
+        }
+        drop(input);
+        let finished = running.join();
+        // with no more input, `a` fails, and `b` then runs dry behind it
+        for f in &finished {
+            assert!(matches!(f.result, Err(MachineError::InputExhausted { .. })));
+        }
+        assert!(finished[1].machine.output == vec![4, 8, 12]);
+    }
+
+    #[test]
+    fn test_waiting_on_closed_input() {
+        let mut machine = Machine::new("3,0,4,0,3,0,99", vec![5]);
+        machine.wait_on_input();
+        let mut network = Network::new();
+        network.add(machine);
+        let finished = network.run();
+        assert!(finished[0].result.as_ref().unwrap() == &Status::Waiting);
+        assert!(finished[0].machine.output == vec![5]);
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut network = Network::new();
+        // loops forever
+        network.add(Machine::new("1105,1,0", vec![]));
+        // waits forever on input that is never sent
+        let waiter = network.add(Machine::new("3,0,99", vec![]));
+        let _input = network.input(waiter);
+        network.add(Machine::new("104,7,99", vec![]));
+        let running = network.spawn();
+        thread::sleep(Duration::from_millis(50));
+        let finished = running.stop();
+        assert!(finished[0].result.as_ref().unwrap() == &Status::BudgetExhausted);
+        assert!(finished[1].result.as_ref().unwrap() == &Status::BudgetExhausted);
+        assert!(finished[1].machine.mem_ptr() == 0);
+        assert!(finished[2].result.as_ref().unwrap() == &Status::Halted);
+
+        // a budget of the machine's own still runs out as usual
+        let mut machine = Machine::new("1105,1,0", vec![]);
+        machine.set_instruction_budget(Some(25_000));
+        let mut network = Network::new();
+        network.add(machine);
+        let finished = network.run();
+        assert!(finished[0].result.as_ref().unwrap() == &Status::BudgetExhausted);
+        assert!(finished[0].machine.instruction_budget() == Some(0));
+    }
+}
@@ -0,0 +1,360 @@
+//! A peephole optimizer, which rewrites the code of a program in place.
+//!
+//! Instructions keep their addresses and sizes, so data, jump targets and
+//! addresses the program computes stay valid. Only instructions reachable
+//! in the program's control flow graph are rewritten:
+//!
+//! - arithmetic and comparisons of two immediate values become an `ADD` of
+//!   the result and 0, e.g. `MUL #6, #7, @1` becomes `ADD #42, #0, @1`
+//! - a jump on an immediate condition becomes `JT #1, target` if it's always
+//!   taken, or the no-op `JT #0, #0` if it never is
+//! - a jump with an immediate target that lands on an unconditional jump
+//!   with an immediate target, or on a no-op jump, goes straight to where
+//!   that leads instead, following chains of them
+//!
+//! Instructions named by positional parameters, which the program may read
+//! or write as data, are left alone. Where a relative-mode parameter points
+//! isn't known until run time, and it could be into the code, so a program
+//! with a reachable one isn't rewritten at all. Code the program writes
+//! that wasn't already there to be decoded, e.g. an instruction it patches
+//! from its input, can be optimized by calling `optimize` after the patch.
+
+use super::control_flow::control_flow_graph;
+use super::{Instruction, Parameter, ParameterMode};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A program rewritten by `optimize`, which displays as a comma separated
+/// program for `Machine::new`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Optimized {
+    pub memory: Vec<i128>,
+    /// the arithmetic and comparisons replaced by their results
+    pub folded: usize,
+    /// the jumps on immediate conditions made unconditional or into no-ops
+    pub constant_jumps: usize,
+    /// the jumps retargeted past the jumps they landed on
+    pub threaded: usize,
+    /// the address of a reachable instruction with a relative-mode
+    /// parameter, if there is one, in which case nothing was rewritten
+    pub relative_at: Option<usize>,
+}
+
+impl fmt::Display for Optimized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, word) in self.memory.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl Optimized {
+    /// write `instruction` at `address`, returning whether that changed
+    /// anything
+    fn replace(&mut self, address: usize, instruction: &Instruction) -> bool {
+        let words = instruction.encode();
+        let old = &mut self.memory[address..address + words.len()];
+        if *old == *words {
+            return false;
+        }
+        old.copy_from_slice(&words);
+        true
+    }
+}
+
+fn immediate(value: i128) -> Parameter {
+    Parameter::new(value, ParameterMode::Immediate)
+}
+
+fn decode(memory: &[i128], address: usize) -> Option<Instruction> {
+    Instruction::decode(memory.get(address..).unwrap_or(&[]), address).ok()
+}
+
+/// `ADD` of the result and 0, if `instruction` is arithmetic or a comparison
+/// of two immediate values that doesn't overflow
+fn fold(instruction: &Instruction) -> Option<Instruction> {
+    use Instruction::*;
+    let (a, b, c) = match instruction {
+        Add(a, b, c) | Mult(a, b, c) | LessThan(a, b, c) | Equal(a, b, c) => (a, b, c),
+        _ => return None,
+    };
+    if a.mode != ParameterMode::Immediate || b.mode != ParameterMode::Immediate {
+        return None;
+    }
+    let value = match instruction {
+        Add(..) => a.value.checked_add(b.value)?,
+        Mult(..) => a.value.checked_mul(b.value)?,
+        LessThan(..) => i128::from(a.value < b.value),
+        _ => i128::from(a.value == b.value),
+    };
+    Some(Add(immediate(value), immediate(0), *c))
+}
+
+/// whether a jump on an immediate condition is taken, or `None` if
+/// `instruction` isn't one
+fn taken(instruction: &Instruction) -> Option<bool> {
+    let (condition, jump_if) = match instruction {
+        Instruction::JumpTrue(condition, _) => (condition, true),
+        Instruction::JumpFalse(condition, _) => (condition, false),
+        _ => return None,
+    };
+    match condition.mode {
+        ParameterMode::Immediate => Some((condition.value != 0) == jump_if),
+        _ => None,
+    }
+}
+
+/// the immediate target of a jump
+fn target(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::JumpTrue(_, target) | Instruction::JumpFalse(_, target)
+            if target.mode == ParameterMode::Immediate =>
+        {
+            usize::try_from(target.value).ok()
+        }
+        _ => None,
+    }
+}
+
+/// where execution arriving at `address` ends up after any unconditional
+/// jumps and no-ops there, not passing through the `fixed` addresses
+fn destination(memory: &[i128], fixed: &BTreeSet<usize>, mut address: usize) -> usize {
+    let mut seen = BTreeSet::new();
+    // stop at a loop of jumps, which is as good a destination as any
+    while seen.insert(address) {
+        let instruction = match decode(memory, address) {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let size = instruction.size();
+        if fixed.range(address..address + size).next().is_some() {
+            break;
+        }
+        address = match (taken(&instruction), target(&instruction)) {
+            (Some(true), Some(target)) => target,
+            (Some(false), _) => address + size,
+            _ => break,
+        };
+    }
+    address
+}
+
+/// Optimize the code in `memory`, as described in the module documentation
+pub fn optimize(memory: &[i128]) -> Optimized {
+    use Instruction::*;
+    let graph = control_flow_graph(memory);
+    let instructions = graph
+        .blocks
+        .values()
+        .flat_map(|block| block.instructions.iter().cloned())
+        .collect::<Vec<_>>();
+    let relative_at = instructions
+        .iter()
+        .find(|(_, instruction)| {
+            instruction
+                .parameters()
+                .iter()
+                .any(|parameter| parameter.mode == ParameterMode::Relative)
+        })
+        .map(|(address, _)| *address);
+    let mut optimized = Optimized {
+        memory: memory.to_vec(),
+        folded: 0,
+        constant_jumps: 0,
+        threaded: 0,
+        relative_at,
+    };
+    if relative_at.is_some() {
+        return optimized;
+    }
+
+    // the addresses the code uses as data
+    let fixed = instructions
+        .iter()
+        .flat_map(|(_, instruction)| instruction.parameters())
+        .filter(|parameter| parameter.mode == ParameterMode::Positional)
+        .filter_map(|parameter| usize::try_from(parameter.value).ok())
+        .collect::<BTreeSet<_>>();
+    let instructions = instructions
+        .into_iter()
+        .filter(|(address, instruction)| {
+            fixed
+                .range(*address..address + instruction.size())
+                .next()
+                .is_none()
+        })
+        .collect::<Vec<_>>();
+
+    for (address, instruction) in &instructions {
+        if let Some(folded) = fold(instruction) {
+            optimized.folded += optimized.replace(*address, &folded) as usize;
+        }
+        if let (Some(always), JumpTrue(_, target) | JumpFalse(_, target)) =
+            (taken(instruction), instruction)
+        {
+            let settled = match always {
+                true => JumpTrue(immediate(1), *target),
+                false => JumpTrue(immediate(0), immediate(0)),
+            };
+            optimized.constant_jumps += optimized.replace(*address, &settled) as usize;
+        }
+    }
+
+    // thread the jumps as they are now, so chains run through the jumps
+    // just made unconditional
+    for (address, _) in &instructions {
+        let instruction = match decode(&optimized.memory, *address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let start = match target(&instruction) {
+            Some(start) if taken(&instruction) != Some(false) => start,
+            _ => continue,
+        };
+        let end = immediate(destination(&optimized.memory, &fixed, start) as i128);
+        let threaded = match instruction {
+            JumpTrue(condition, _) => JumpTrue(condition, end),
+            JumpFalse(condition, _) => JumpFalse(condition, end),
+            _ => continue,
+        };
+        optimized.threaded += optimized.replace(*address, &threaded) as usize;
+    }
+    optimized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::assembler::assemble_words;
+    use crate::int_code_machine::{Machine, Status};
+
+    /// the output of running `src` and its optimized version on `input`,
+    /// which must be the same
+    fn run_both(src: &str, input: Vec<i128>) -> Vec<i128> {
+        let optimized = optimize(&Machine::new(src, vec![]).memory).to_string();
+        let mut outputs = vec![];
+        for program in &[src, &optimized] {
+            let mut machine = Machine::new(program, input.clone());
+            assert!(machine.run() == Status::Halted);
+            outputs.push(machine.output);
+        }
+        assert!(outputs[0] == outputs[1]);
+        outputs.pop().unwrap()
+    }
+
+    #[test]
+    fn test_fold() {
+        let memory = assemble_words(
+            "
+                    MUL #6, #7, x
+                    LT #2, #1, y
+                    EQ #3, #3, y
+                    ADD #1, #0, y       ; already folded
+                    ADD x, #1, x        ; not immediate
+                    OUT x
+                    OUT y
+                    HLT
+            x:      .data 0
+            y:      .data 0
+            ",
+        )
+        .unwrap();
+        let optimized = optimize(&memory);
+        assert!(optimized.folded == 3);
+        assert!(optimized.memory[..12] == [1101, 42, 0, 25, 1101, 0, 0, 26, 1101, 1, 0, 26]);
+        assert!(optimized.memory[12..] == memory[12..]);
+        assert!(run_both(&optimized.to_string(), vec![]) == vec![43, 1]);
+
+        // overflow is left for run time
+        assert!(optimize(&[1102, 1 << 100, 1 << 100, 0, 99]).folded == 0);
+    }
+
+    #[test]
+    fn test_jumps() {
+        let src = assemble_words(
+            "
+                    IN n
+                    JF #0, #test        ; always taken
+            skip:   JT #1, #skip
+            test:   JT n, #hop          ; threaded to `done`
+                    JT #0, #skip        ; never taken
+                    OUT #0
+            hop:    JT #2, #nop
+            nop:    JF #1, #hop         ; a no-op
+            done:   OUT n
+                    HLT
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+        let optimized = optimize(&src);
+        assert!(optimized.constant_jumps == 4);
+        assert!(optimized.threaded == 2);
+        let jump = |address: usize| optimized.memory[address..address + 3].to_vec();
+        assert!(jump(2) == [1105, 1, 8]);
+        assert!(jump(5) == [1105, 1, 5]);
+        assert!(jump(8) == [1005, 25, 22]);
+        assert!(jump(11) == [1105, 0, 0]);
+        assert!(jump(16) == [1105, 1, 22]);
+        assert!(jump(19) == [1105, 0, 0]);
+        for input in 0..2 {
+            let program = src.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+            let expected = if input == 0 { vec![0, 0] } else { vec![1] };
+            assert!(run_both(&program.join(","), vec![input]) == expected);
+        }
+    }
+
+    #[test]
+    fn test_code_used_as_data() {
+        // outputs the 2 in its first instruction, which can't be folded
+        let src = "1101,2,3,7,4,1,99,0";
+        let optimized = optimize(&Machine::new(src, vec![]).memory);
+        assert!(optimized.to_string() == src);
+        assert!(run_both(src, vec![]) == vec![2]);
+    }
+
+    #[test]
+    fn test_relative_reads() {
+        // outputs the 3 in its `ADD` through a relative-mode parameter, so
+        // folding that would change the output
+        let src = "109,0,1101,2,3,20,204,4,99";
+        let optimized = optimize(&Machine::new(src, vec![]).memory);
+        assert!(optimized.relative_at == Some(6));
+        assert!(optimized.to_string() == src);
+        assert!(run_both(src, vec![]) == vec![3]);
+    }
+
+    #[test]
+    fn test_day_programs() {
+        // day 5 patches the instruction at 6 from its input, so its code
+        // can only be followed past there once the first two have run
+        let day5 = include_str!("../../../day5/src/input/input1").trim();
+        for &(input, ref expected) in &[
+            (1, vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 13294380]),
+            (5, vec![11460760]),
+        ] {
+            let mut machine = Machine::new(day5, vec![input]);
+            machine.step().unwrap();
+            machine.step().unwrap();
+            let optimized = optimize(&machine.memory);
+            assert!(optimized.relative_at.is_none());
+            assert!(optimized.folded + optimized.constant_jumps + optimized.threaded > 0);
+            machine.memory = optimized.memory;
+            assert!(machine.run() == Status::Halted);
+            assert!(machine.output == *expected);
+        }
+
+        // day 9 keeps its stack through relative-mode parameters, so is left as it is
+        let day9 = include_str!("../../../day9/src/input/input").trim();
+        let optimized = optimize(&Machine::new(day9, vec![]).memory);
+        assert!(optimized.relative_at.is_some());
+        assert!(optimized.to_string() == day9);
+        assert!(run_both(day9, vec![1]) == vec![2171728567]);
+        assert!(run_both(day9, vec![2]) == vec![49815]);
+    }
+}
@@ -0,0 +1,295 @@
+use super::{Instruction, Machine, MachineError, Status, Step, StepOutcome};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// how many of the hottest addresses and jump sites the table lists
+const TABLE_ROWS: usize = 10;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub executions: u64,
+    /// the instruction last executed here, which only changes if the
+    /// program modifies itself
+    pub mnemonic: &'static str,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct JumpStats {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Execution counts gathered by a `Profiler`.
+///
+/// `Display` prints a summary table; `to_json` exports everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub instructions: u64,
+    /// executions of each opcode, by mnemonic
+    pub opcodes: BTreeMap<&'static str, u64>,
+    pub addresses: BTreeMap<usize, AddressStats>,
+    /// the outcomes of the jump instruction at each address
+    pub jumps: BTreeMap<usize, JumpStats>,
+    pub inputs: u64,
+    pub outputs: u64,
+}
+
+impl Profile {
+    pub fn record(&mut self, step: &Step) {
+        let mnemonic = step.instruction.mnemonic();
+        self.instructions += 1;
+        *self.opcodes.entry(mnemonic).or_insert(0) += 1;
+        let address = self.addresses.entry(step.address).or_default();
+        address.executions += 1;
+        address.mnemonic = mnemonic;
+        if let Instruction::JumpTrue(..) | Instruction::JumpFalse(..) = step.instruction {
+            let jump = self.jumps.entry(step.address).or_default();
+            if step.jumped {
+                jump.taken += 1;
+            } else {
+                jump.not_taken += 1;
+            }
+        }
+        self.inputs += step.input.is_some() as u64;
+        self.outputs += step.output.is_some() as u64;
+    }
+
+    /// the `n` most executed addresses, most executed first
+    pub fn hot_addresses(&self, n: usize) -> Vec<(usize, AddressStats)> {
+        let mut hot = self
+            .addresses
+            .iter()
+            .map(|(&address, &stats)| (address, stats))
+            .collect::<Vec<_>>();
+        hot.sort_by_key(|&(address, stats)| (std::cmp::Reverse(stats.executions), address));
+        hot.truncate(n);
+        hot
+    }
+
+    /// the total (taken, not taken) over every jump
+    pub fn jump_totals(&self) -> (u64, u64) {
+        self.jumps
+            .values()
+            .fold((0, 0), |(taken, not_taken), jump| {
+                (taken + jump.taken, not_taken + jump.not_taken)
+            })
+    }
+
+    /// the whole profile as a single JSON object, e.g.
+    /// `{"instructions":3,"inputs":1,"outputs":1,"opcodes":{"HLT":1,...},`
+    /// `"addresses":[{"address":0,"executions":1,"mnemonic":"IN"},...],`
+    /// `"jumps":[{"address":4,"taken":1,"not_taken":0},...]}`
+    pub fn to_json(&self) -> String {
+        // mnemonics and numbers never need escaping
+        let opcodes = self
+            .opcodes
+            .iter()
+            .map(|(mnemonic, count)| format!(r#""{}":{}"#, mnemonic, count))
+            .collect::<Vec<_>>()
+            .join(",");
+        let addresses = self
+            .addresses
+            .iter()
+            .map(|(address, stats)| {
+                format!(
+                    r#"{{"address":{},"executions":{},"mnemonic":"{}"}}"#,
+                    address, stats.executions, stats.mnemonic
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let jumps = self
+            .jumps
+            .iter()
+            .map(|(address, jump)| {
+                format!(
+                    r#"{{"address":{},"taken":{},"not_taken":{}}}"#,
+                    address, jump.taken, jump.not_taken
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            concat!(
+                r#"{{"instructions":{},"inputs":{},"outputs":{},"opcodes":{{{}}},"#,
+                r#""addresses":[{}],"jumps":[{}]}}"#
+            ),
+            self.instructions, self.inputs, self.outputs, opcodes, addresses, jumps
+        )
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            100.0 * count as f64 / self.instructions as f64
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (taken, not_taken) = self.jump_totals();
+        writeln!(f, "instructions {:>12}", self.instructions)?;
+        writeln!(f, "inputs       {:>12}", self.inputs)?;
+        writeln!(f, "outputs      {:>12}", self.outputs)?;
+        writeln!(f, "jumps taken  {:>12}", taken)?;
+        writeln!(f, "not taken    {:>12}", not_taken)?;
+
+        writeln!(f, "\nopcode             count       %")?;
+        let mut opcodes = self.opcodes.iter().collect::<Vec<_>>();
+        opcodes.sort_by_key(|&(mnemonic, &count)| (std::cmp::Reverse(count), *mnemonic));
+        for (mnemonic, &count) in opcodes {
+            writeln!(
+                f,
+                "{:<6} {:>15} {:>6.2}",
+                mnemonic,
+                count,
+                self.percent(count)
+            )?;
+        }
+
+        writeln!(f, "\naddress            count       %  instruction")?;
+        for (address, stats) in self.hot_addresses(TABLE_ROWS) {
+            writeln!(
+                f,
+                "{:>7} {:>14} {:>6.2}  {}",
+                address,
+                stats.executions,
+                self.percent(stats.executions),
+                stats.mnemonic
+            )?;
+        }
+
+        writeln!(f, "\njump               taken       not taken")?;
+        let mut jumps = self.jumps.iter().collect::<Vec<_>>();
+        jumps.sort_by_key(|&(&address, jump)| {
+            (std::cmp::Reverse(jump.taken + jump.not_taken), address)
+        });
+        for (address, jump) in jumps.into_iter().take(TABLE_ROWS) {
+            writeln!(
+                f,
+                "{:>7} {:>14} {:>15}",
+                address, jump.taken, jump.not_taken
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a `Machine` one instruction at a time, counting what it executes.
+///
+/// Like a `Tracer`, a `Profiler` can follow a machine through several
+/// `Waiting` pauses, adding to the same `Profile`.
+#[derive(Default)]
+pub struct Profiler {
+    profile: Profile,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn into_profile(self) -> Profile {
+        self.profile
+    }
+
+    /// execute and count a single instruction
+    pub fn step(&mut self, machine: &mut Machine) -> Result<StepOutcome, MachineError> {
+        let outcome = machine.step()?;
+        if let StepOutcome::Executed(step) = &outcome {
+            self.profile.record(step);
+        }
+        Ok(outcome)
+    }
+
+    /// profile `machine` until it halts or waits for input
+    pub fn run(&mut self, machine: &mut Machine) -> Result<Status, MachineError> {
+        loop {
+            match self.step(machine)? {
+                StepOutcome::Executed(_) => {}
+                StepOutcome::Waiting => return Ok(Status::Waiting),
+                StepOutcome::Halted => return Ok(Status::Halted),
+            }
+        }
+    }
+}
+
+impl Machine {
+    /// like `try_run`, but also returns a profile of what was executed
+    pub fn run_profiled(&mut self) -> Result<(Status, Profile), MachineError> {
+        let mut profiler = Profiler::new();
+        let status = profiler.run(self)?;
+        Ok((status, profiler.into_profile()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // reads a count, then outputs it counting down to 1
+    const COUNTDOWN: &str = "3,13,4,13,1001,13,-1,13,1005,13,2,99,0,0";
+
+    #[test]
+    fn test_profile_counts() {
+        let mut machine = Machine::new(COUNTDOWN, vec![3]);
+        let (status, profile) = machine.run_profiled().unwrap();
+        assert!(status == Status::Halted);
+        assert!(machine.output == vec![3, 2, 1]);
+        assert!(profile.instructions == 10);
+        assert!(profile.inputs == 1 && profile.outputs == 3);
+        assert!(profile.opcodes["OUT"] == 3 && profile.opcodes["JT"] == 3);
+        assert!(profile.opcodes["IN"] == 1 && !profile.opcodes.contains_key("HLT"));
+        assert!(
+            profile.jumps[&8]
+                == JumpStats {
+                    taken: 2,
+                    not_taken: 1
+                }
+        );
+        assert!(profile.jump_totals() == (2, 1));
+        let hot = profile.hot_addresses(2);
+        assert!(hot.len() == 2 && hot[0].0 == 2 && hot[1].0 == 4);
+        assert!(hot[0].1.executions == 3 && hot[0].1.mnemonic == "OUT");
+    }
+
+    #[test]
+    fn test_profile_resumes() {
+        let mut machine = Machine::new("3,0,4,0,3,0,4,0,99", vec![1]);
+        machine.wait_on_input();
+        let mut profiler = Profiler::new();
+        assert!(profiler.run(&mut machine).unwrap() == Status::Waiting);
+        machine.add_input(2);
+        assert!(profiler.run(&mut machine).unwrap() == Status::Halted);
+        let profile = profiler.into_profile();
+        assert!(profile.instructions == 4 && profile.inputs == 2);
+    }
+
+    #[test]
+    fn test_profile_reports() {
+        let mut machine = Machine::new(COUNTDOWN, vec![1]);
+        let (_, profile) = machine.run_profiled().unwrap();
+        assert!(
+            profile.to_json()
+                == concat!(
+                    r#"{"instructions":4,"inputs":1,"outputs":1,"#,
+                    r#""opcodes":{"ADD":1,"IN":1,"JT":1,"OUT":1},"#,
+                    r#""addresses":[{"address":0,"executions":1,"mnemonic":"IN"},"#,
+                    r#"{"address":2,"executions":1,"mnemonic":"OUT"},"#,
+                    r#"{"address":4,"executions":1,"mnemonic":"ADD"},"#,
+                    r#"{"address":8,"executions":1,"mnemonic":"JT"}],"#,
+                    r#""jumps":[{"address":8,"taken":0,"not_taken":1}]}"#
+                )
+        );
+        let table = profile.to_string();
+        assert!(table.starts_with("instructions            4\n"));
+        assert!(table.contains("\nIN                   1  25.00\n"));
+        assert!(table.contains("\n      8              0               1\n"));
+    }
+}
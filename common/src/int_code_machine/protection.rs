@@ -0,0 +1,239 @@
+use super::hooks::inclusive_bounds;
+use super::{Machine, Memory, Word};
+use std::collections::BTreeSet;
+use std::ops::RangeBounds;
+
+/// A write to the opcode of an instruction that had already been executed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SelfModification<W = i128> {
+    /// the address of the instruction doing the write
+    pub address: usize,
+    pub target: usize,
+    pub old: W,
+    pub new: W,
+}
+
+/// the addresses executed as opcodes so far, and the writes made to them
+#[derive(Clone, Debug)]
+pub(super) struct SelfModificationWatch<W> {
+    executed: BTreeSet<usize>,
+    found: Vec<SelfModification<W>>,
+}
+
+/// The write-protected addresses of a machine, as sorted (inclusive) ranges
+/// which neither overlap nor touch
+#[derive(Clone, Debug, Default)]
+pub(super) struct Protection {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl Protection {
+    fn add(&mut self, mut first: usize, mut last: usize) {
+        // swallow every range overlapping or touching the new one
+        self.ranges.retain(|&(f, l)| {
+            let apart = l.saturating_add(1) < first || last.saturating_add(1) < f;
+            if !apart {
+                first = first.min(f);
+                last = last.max(l);
+            }
+            apart
+        });
+        let i = self.ranges.partition_point(|&(f, _)| f < first);
+        self.ranges.insert(i, (first, last));
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub(super) fn covers(&self, address: usize) -> bool {
+        if self.ranges.is_empty() {
+            return false;
+        }
+        match self.ranges.partition_point(|&(f, _)| f <= address) {
+            0 => false,
+            i => address <= self.ranges[i - 1].1,
+        }
+    }
+}
+
+impl<W: Word, M: Memory<Word = W>> Machine<M> {
+    /// start noting every write an instruction makes to the opcode of an
+    /// instruction that has already run, which `self_modifications` lists.
+    /// Anything already noted is forgotten.
+    ///
+    /// Patching a program before it runs, like day 2's noun and verb, isn't
+    /// self-modification, and neither is writing to a parameter.
+    pub fn watch_for_self_modification(&mut self) {
+        self.self_modification = Some(SelfModificationWatch {
+            executed: BTreeSet::new(),
+            found: vec![],
+        });
+    }
+
+    pub fn stop_watching_for_self_modification(&mut self) {
+        self.self_modification = None;
+    }
+
+    /// the writes to executed opcodes seen since
+    /// `watch_for_self_modification`, oldest first
+    pub fn self_modifications(&self) -> &[SelfModification<W>] {
+        self.self_modification
+            .as_ref()
+            .map_or(&[], |watch| &watch.found)
+    }
+
+    pub(super) fn note_execution(&mut self, address: usize) {
+        if let Some(watch) = &mut self.self_modification {
+            watch.executed.insert(address);
+        }
+    }
+
+    /// note a write by the instruction at `mem_ptr`
+    pub(super) fn note_write(&mut self, target: usize, old: &W, new: &W) {
+        if let Some(watch) = &mut self.self_modification {
+            if watch.executed.contains(&target) {
+                watch.found.push(SelfModification {
+                    address: self.mem_ptr,
+                    target,
+                    old: old.clone(),
+                    new: new.clone(),
+                });
+            }
+        }
+    }
+
+    /// make instructions that write to any of `addresses` fail with
+    /// `MachineError::ProtectedWrite`, leaving the machine as it was before
+    /// the instruction. Writes through the public `memory` aren't affected.
+    pub fn protect(&mut self, addresses: impl RangeBounds<usize>) {
+        if let Some((first, last)) = inclusive_bounds(addresses) {
+            self.protection.add(first, last);
+        }
+    }
+
+    pub fn is_protected(&self, address: usize) -> bool {
+        self.protection.covers(address)
+    }
+
+    /// make every address writable again
+    pub fn clear_protection(&mut self) {
+        self.protection = Protection::default();
+    }
+}
+
+impl Machine {
+    /// protect the words of every instruction in the machine's
+    /// `control_flow_graph`, so that a program which runs away and writes
+    /// over its own code stops at the first such write. Data in the program
+    /// image stays writable, but code that's deliberately self-modifying,
+    /// like a day 2 program storing its result at address 0, will fail too.
+    pub fn protect_code(&mut self) {
+        let graph = self.control_flow_graph();
+        for block in graph.blocks.values() {
+            for (address, instruction) in &block.instructions {
+                self.protect(*address..*address + instruction.size());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::{MachineError, Status};
+
+    // stores 5 at 12, then overwrites the first instruction with a halt and
+    // jumps back to it
+    const OVERWRITES_ITSELF: &str = "1101,2,3,12,1101,0,99,0,1105,1,0,0,0";
+
+    #[test]
+    fn test_self_modification() {
+        let mut machine = Machine::new(OVERWRITES_ITSELF, vec![]);
+        machine.watch_for_self_modification();
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.memory[12] == 5);
+        assert!(
+            machine.self_modifications()
+                == [SelfModification {
+                    address: 4,
+                    target: 0,
+                    old: 1101,
+                    new: 99,
+                }]
+        );
+
+        // patching an instruction before it runs isn't self-modification
+        let mut machine = Machine::new("1101,0,99,4,0", vec![]);
+        machine.watch_for_self_modification();
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.self_modifications().is_empty());
+        machine.stop_watching_for_self_modification();
+        assert!(machine.self_modifications().is_empty());
+    }
+
+    #[test]
+    fn test_instruction_overwriting_itself() {
+        // writes 5 over its own opcode
+        let mut machine = Machine::new("1101,5,0,0,99", vec![]);
+        machine.watch_for_self_modification();
+        assert!(machine.run() == Status::Halted);
+        assert!(
+            machine.self_modifications()
+                == [SelfModification {
+                    address: 0,
+                    target: 0,
+                    old: 1101,
+                    new: 5,
+                }]
+        );
+    }
+
+    #[test]
+    fn test_protect() {
+        let mut machine = Machine::new(OVERWRITES_ITSELF, vec![]);
+        machine.protect(12..13);
+        assert!(
+            machine.try_run()
+                == Err(MachineError::ProtectedWrite {
+                    address: 0,
+                    opcode: 1101,
+                    target: 12,
+                })
+        );
+        assert!(machine.memory[12] == 0 && machine.mem_ptr() == 0);
+
+        // a protected input destination leaves the input unread
+        let mut machine = Machine::new("3,3,99,0", vec![7]);
+        machine.protect(3..=3);
+        assert!(machine.try_run().is_err() && machine.input_ptr() == 0);
+        machine.clear_protection();
+        assert!(machine.run() == Status::Halted && machine.memory[3] == 7);
+
+        machine.protect(5..10);
+        machine.protect(0..3);
+        machine.protect(2..6);
+        machine.protect(20..20);
+        let protected = (0..25)
+            .filter(|&a| machine.is_protected(a))
+            .collect::<Vec<_>>();
+        assert!(protected == (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_protect_code() {
+        let mut machine = Machine::new(OVERWRITES_ITSELF, vec![]);
+        machine.protect_code();
+        assert!((0..=10).all(|a| machine.is_protected(a)));
+        assert!(!machine.is_protected(12));
+        assert!(
+            machine.try_run()
+                == Err(MachineError::ProtectedWrite {
+                    address: 4,
+                    opcode: 1101,
+                    target: 0,
+                })
+        );
+        assert!(machine.memory[12] == 5);
+    }
+}
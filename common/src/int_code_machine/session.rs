@@ -0,0 +1,399 @@
+//! Recording a `Machine`'s I/O and replaying it as a regression check.
+//!
+//! A `SessionRecorder` runs a machine like a `Tracer`, noting each value read
+//! by an `IN` and written by an `OUT` along with the step it happened on. The
+//! resulting `Session` saves as text, one event per line after a version
+//! header, e.g.
+//!
+//! ```text
+//! intcode-session 1
+//! in 1 5
+//! out 4 25
+//! ```
+//!
+//! `Session::replay` then runs a fresh machine on the recorded input and
+//! checks that every read and write happens at the same step with the same
+//! value.
+
+use super::{Machine, MachineError, Status, Step, StepOutcome};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const HEADER: &str = "intcode-session";
+const VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// the value read by the input instruction at step `step`
+    Input {
+        step: u64,
+        value: i128,
+    },
+    Output {
+        step: u64,
+        value: i128,
+    },
+}
+
+impl Event {
+    /// the I/O event of an executed instruction, if it has one
+    fn of(step_number: u64, step: &Step) -> Option<Event> {
+        match (step.input, step.output) {
+            (Some(value), _) => Some(Event::Input {
+                step: step_number,
+                value,
+            }),
+            (_, Some(value)) => Some(Event::Output {
+                step: step_number,
+                value,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Written as it appears in a saved session, e.g. `in 3 0`
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Input { step, value } => write!(f, "in {} {}", step, value),
+            Event::Output { step, value } => write!(f, "out {} {}", step, value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    /// the data doesn't start with a session header
+    UnknownFormat,
+    UnsupportedVersion(u32),
+    Corrupt(String),
+}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Io(e) => write!(f, "session i/o failed: {}", e),
+            SessionError::UnknownFormat => write!(f, "not a recorded session"),
+            SessionError::UnsupportedVersion(v) => {
+                write!(f, "unsupported session version {}", v)
+            }
+            SessionError::Corrupt(reason) => write!(f, "corrupt session: {}", reason),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    Machine(MachineError),
+    /// the machine did something other than the recorded event, or did
+    /// something after the recording ended if `expected` is `None`
+    Diverged {
+        expected: Option<Event>,
+        actual: Event,
+    },
+    /// the machine stopped before reaching the recorded event
+    Unfinished {
+        status: Status,
+        expected: Event,
+    },
+}
+
+impl From<MachineError> for ReplayError {
+    fn from(e: MachineError) -> Self {
+        ReplayError::Machine(e)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Machine(e) => write!(f, "{}", e),
+            ReplayError::Diverged {
+                expected: Some(expected),
+                actual,
+            } => write!(f, "replay diverged: expected {}, got {}", expected, actual),
+            ReplayError::Diverged {
+                expected: None,
+                actual,
+            } => write!(
+                f,
+                "replay diverged: got {} after the recording ended",
+                actual
+            ),
+            ReplayError::Unfinished { status, expected } => write!(
+                f,
+                "replay stopped ({:?}) before the recorded {}",
+                status, expected
+            ),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+/// The I/O of a machine run, in the order it happened
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+impl Session {
+    /// the values the machine read
+    pub fn inputs(&self) -> impl Iterator<Item = i128> + '_ {
+        self.events.iter().filter_map(|event| match event {
+            Event::Input { value, .. } => Some(*value),
+            Event::Output { .. } => None,
+        })
+    }
+
+    /// the values the machine wrote
+    pub fn outputs(&self) -> impl Iterator<Item = i128> + '_ {
+        self.events.iter().filter_map(|event| match event {
+            Event::Output { value, .. } => Some(*value),
+            Event::Input { .. } => None,
+        })
+    }
+
+    pub fn save(&self, out: &mut impl Write) -> Result<(), SessionError> {
+        writeln!(out, "{} {}", HEADER, VERSION)?;
+        for event in &self.events {
+            writeln!(out, "{}", event)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(mut input: impl Read) -> Result<Session, SessionError> {
+        let mut text = String::new();
+        input
+            .read_to_string(&mut text)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidData => corrupt("text is not UTF-8"),
+                _ => SessionError::Io(e),
+            })?;
+        let mut lines = text.lines();
+        let version = lines
+            .next()
+            .and_then(|header| header.strip_prefix(HEADER))
+            .ok_or(SessionError::UnknownFormat)?;
+        let version = version
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| corrupt("bad header"))?;
+        if version != VERSION {
+            return Err(SessionError::UnsupportedVersion(version));
+        }
+        let events = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_event)
+            .collect::<Result<_, _>>()?;
+        Ok(Session { events })
+    }
+
+    /// run `machine`, which should be in the state the recording started
+    /// from, on the recorded input and check it does exactly what was
+    /// recorded. Returns how the machine stopped.
+    pub fn replay(&self, machine: &mut Machine) -> Result<Status, ReplayError> {
+        for value in self.inputs() {
+            machine.add_input(value);
+        }
+        machine.wait_on_input();
+        let mut expected = self.events.iter();
+        let mut steps = 0;
+        loop {
+            let status = match machine.step()? {
+                StepOutcome::Executed(step) => {
+                    if let Some(actual) = Event::of(steps, &step) {
+                        match expected.next() {
+                            Some(&event) if event == actual => {}
+                            event => {
+                                return Err(ReplayError::Diverged {
+                                    expected: event.cloned(),
+                                    actual,
+                                })
+                            }
+                        }
+                    }
+                    steps += 1;
+                    continue;
+                }
+                StepOutcome::Waiting => Status::Waiting,
+                StepOutcome::Halted => Status::Halted,
+            };
+            return match expected.next() {
+                Some(&event) => Err(ReplayError::Unfinished {
+                    status,
+                    expected: event,
+                }),
+                None => Ok(status),
+            };
+        }
+    }
+}
+
+fn corrupt(reason: impl Into<String>) -> SessionError {
+    SessionError::Corrupt(reason.into())
+}
+
+fn parse_event(line: &str) -> Result<Event, SessionError> {
+    let bad = || corrupt(format!("bad event {:?}", line));
+    let mut fields = line.split_whitespace();
+    let kind = fields.next().ok_or_else(bad)?;
+    let step = fields.next().and_then(|s| s.parse().ok()).ok_or_else(bad)?;
+    let value = fields.next().and_then(|v| v.parse().ok()).ok_or_else(bad)?;
+    if fields.next().is_some() {
+        return Err(bad());
+    }
+    match kind {
+        "in" => Ok(Event::Input { step, value }),
+        "out" => Ok(Event::Output { step, value }),
+        _ => Err(bad()),
+    }
+}
+
+/// Runs a `Machine`, recording its I/O into a `Session`.
+///
+/// Like a `Tracer`, step numbers carry on across calls, so a session can
+/// span all the `Waiting` pauses of an interactive program.
+#[derive(Default)]
+pub struct SessionRecorder {
+    session: Session,
+    steps: u64,
+}
+
+impl SessionRecorder {
+    pub fn new() -> Self {
+        SessionRecorder::default()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn into_session(self) -> Session {
+        self.session
+    }
+
+    /// execute a single instruction, recording its I/O
+    pub fn step(&mut self, machine: &mut Machine) -> Result<StepOutcome, MachineError> {
+        let outcome = machine.step()?;
+        if let StepOutcome::Executed(step) = &outcome {
+            self.session.events.extend(Event::of(self.steps, step));
+            self.steps += 1;
+        }
+        Ok(outcome)
+    }
+
+    /// record `machine` until it halts or waits for input
+    pub fn run(&mut self, machine: &mut Machine) -> Result<Status, MachineError> {
+        loop {
+            match self.step(machine)? {
+                StepOutcome::Executed(_) => {}
+                StepOutcome::Waiting => return Ok(Status::Waiting),
+                StepOutcome::Halted => return Ok(Status::Halted),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // outputs the square of each input until it reads a 0
+    const SQUARES: &str = "3,15,1006,15,14,2,15,15,16,4,16,1105,1,0,99,0,0";
+
+    fn record(inputs: &[i128]) -> Session {
+        let mut machine = Machine::new(SQUARES, vec![]);
+        machine.wait_on_input();
+        let mut recorder = SessionRecorder::new();
+        for &input in inputs {
+            assert!(recorder.run(&mut machine).unwrap() == Status::Waiting);
+            machine.add_input(input);
+        }
+        recorder.run(&mut machine).unwrap();
+        recorder.into_session()
+    }
+
+    #[test]
+    fn test_record() {
+        let session = record(&[3, 0]);
+        assert!(
+            session.events
+                == vec![
+                    Event::Input { step: 0, value: 3 },
+                    Event::Output { step: 3, value: 9 },
+                    Event::Input { step: 5, value: 0 },
+                ]
+        );
+        assert!(session.inputs().collect::<Vec<_>>() == vec![3, 0]);
+        assert!(session.outputs().collect::<Vec<_>>() == vec![9]);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let session = record(&[3, -4, 0]);
+        let mut out = vec![];
+        session.save(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("intcode-session 1\nin 0 3\nout 3 9\nin 5 -4\n"));
+        assert!(Session::load(text.as_bytes()).unwrap() == session);
+
+        assert!(matches!(
+            Session::load(&b"intcode-snapshot 1\n"[..]),
+            Err(SessionError::UnknownFormat)
+        ));
+        assert!(matches!(
+            Session::load(&b"intcode-session 7\n"[..]),
+            Err(SessionError::UnsupportedVersion(7))
+        ));
+        assert!(matches!(
+            Session::load(&b"intcode-session 1\nin 0\n"[..]),
+            Err(SessionError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_replay() {
+        let session = record(&[3, 5, 0]);
+        let mut machine = Machine::new(SQUARES, vec![]);
+        assert!(session.replay(&mut machine) == Ok(Status::Halted));
+        assert!(machine.output == vec![9, 25]);
+
+        // a program that cubes instead diverges at the first output
+        let cubes = "3,19,1006,19,18,2,19,19,20,2,19,20,20,4,20,1105,1,0,99,0,0";
+        let mut machine = Machine::new(cubes, vec![]);
+        assert!(matches!(
+            session.replay(&mut machine),
+            Err(ReplayError::Diverged {
+                expected: Some(Event::Output { step: 3, value: 9 }),
+                actual: Event::Output { step: 4, value: 27 },
+            })
+        ));
+
+        // a recording cut short leaves the machine asking for more
+        let mut short = session.clone();
+        short.events.truncate(2);
+        let mut machine = Machine::new(SQUARES, vec![]);
+        assert!(short.replay(&mut machine) == Ok(Status::Waiting));
+        short.events.push(Event::Output { step: 9, value: 1 });
+        let mut machine = Machine::new(SQUARES, vec![]);
+        assert!(matches!(
+            short.replay(&mut machine),
+            Err(ReplayError::Unfinished {
+                status: Status::Waiting,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+//! Saving and restoring a `Machine`'s state.
+//!
+//! A snapshot records memory, the instruction pointer, the relative base,
+//! input that hasn't been read yet, output, whether the machine waits on
+//! empty input and its `Arithmetic`. There are two encodings, both starting
+//! with a version number:
+//!
+//! * binary: the magic bytes `ICMS`, a version byte, a flags byte (bit 0 for
+//!   waiting on input, bits 1 and 2 for the arithmetic), then
+//!   `mem_ptr`, `relative_base` and the memory, input and output lists as
+//!   LEB128 varints (signed values zigzag encoded, lists prefixed by length)
+//! * text: one `key value` line per field after an `intcode-snapshot <version>`
+//!   header, with lists written comma separated, e.g.
+//!
+//! ```text
+//! intcode-snapshot 1
+//! mem_ptr 2
+//! relative_base 0
+//! await_input true
+//! arithmetic checked
+//! memory 3,0,99
+//! input 5,6
+//! output
+//! ```
+//!
+//! `Machine::load` detects which encoding it's given. Instruction budgets,
+//! deadlines, history and hooks aren't saved; a loaded machine has none.
+
+use super::{Arithmetic, Machine};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8] = b"ICMS";
+const TEXT_HEADER: &str = "intcode-snapshot";
+const VERSION: u8 = 1;
+
+const FLAG_AWAIT_INPUT: u8 = 1;
+const ARITHMETIC_SHIFT: u8 = 1;
+const ARITHMETIC_MASK: u8 = 0b11 << ARITHMETIC_SHIFT;
+
+const ARITHMETICS: [(Arithmetic, &str); 3] = [
+    (Arithmetic::Checked, "checked"),
+    (Arithmetic::Wrapping, "wrapping"),
+    (Arithmetic::Saturating, "saturating"),
+];
+
+fn arithmetic_code(arithmetic: Arithmetic) -> usize {
+    ARITHMETICS
+        .iter()
+        .position(|&(a, _)| a == arithmetic)
+        .unwrap()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Binary,
+    Text,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// the data doesn't start like either encoding
+    UnknownFormat,
+    UnsupportedVersion(u32),
+    /// the data is truncated or malformed
+    Corrupt(String),
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot i/o failed: {}", e),
+            SnapshotError::UnknownFormat => write!(f, "not a machine snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {}", v)
+            }
+            SnapshotError::Corrupt(reason) => write!(f, "corrupt snapshot: {}", reason),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+fn corrupt(reason: impl Into<String>) -> SnapshotError {
+    SnapshotError::Corrupt(reason.into())
+}
+
+impl Machine {
+    /// write a binary snapshot of the machine to `out`
+    pub fn save(&self, out: &mut impl Write) -> Result<(), SnapshotError> {
+        self.save_as(out, Encoding::Binary)
+    }
+
+    pub fn save_as(&self, out: &mut impl Write, encoding: Encoding) -> Result<(), SnapshotError> {
+        match encoding {
+            Encoding::Binary => out.write_all(&self.to_binary())?,
+            Encoding::Text => out.write_all(self.to_text().as_bytes())?,
+        }
+        Ok(())
+    }
+
+    /// restore a machine from a snapshot in either encoding
+    pub fn load(mut input: impl Read) -> Result<Machine, SnapshotError> {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        if data.starts_with(MAGIC) {
+            from_binary(&data[MAGIC.len()..])
+        } else if data.starts_with(TEXT_HEADER.as_bytes()) {
+            let text = std::str::from_utf8(&data).map_err(|_| corrupt("text is not UTF-8"))?;
+            from_text(text)
+        } else {
+            Err(SnapshotError::UnknownFormat)
+        }
+    }
+
+    fn pending_input(&self) -> &[i128] {
+        &self.input[self.input_ptr..]
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        let mut flags = (arithmetic_code(self.arithmetic) as u8) << ARITHMETIC_SHIFT;
+        if self.await_empty_input {
+            flags |= FLAG_AWAIT_INPUT;
+        }
+        out.push(flags);
+        write_unsigned(&mut out, self.mem_ptr as u128);
+        write_signed(&mut out, self.relative_base as i128);
+        for list in [&self.memory[..], self.pending_input(), &self.output] {
+            write_unsigned(&mut out, list.len() as u128);
+            for &value in list {
+                write_signed(&mut out, value);
+            }
+        }
+        out
+    }
+
+    fn to_text(&self) -> String {
+        let join = |values: &[i128]| {
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            concat!(
+                "{} {}\nmem_ptr {}\nrelative_base {}\nawait_input {}\narithmetic {}\n",
+                "memory {}\ninput {}\noutput {}\n"
+            ),
+            TEXT_HEADER,
+            VERSION,
+            self.mem_ptr,
+            self.relative_base,
+            self.await_empty_input,
+            ARITHMETICS[arithmetic_code(self.arithmetic)].1,
+            join(&self.memory),
+            join(self.pending_input()),
+            join(&self.output),
+        )
+    }
+}
+
+fn restore(
+    memory: Vec<i128>,
+    mem_ptr: usize,
+    relative_base: isize,
+    input: Vec<i128>,
+    output: Vec<i128>,
+    await_empty_input: bool,
+    arithmetic: Arithmetic,
+) -> Machine {
+    Machine {
+        memory,
+        mem_ptr,
+        input,
+        input_ptr: 0,
+        output,
+        await_empty_input,
+        arithmetic,
+        relative_base,
+        instruction_budget: None,
+        deadline: None,
+        until_clock_check: 0,
+        history: None,
+        hooks: Default::default(),
+        self_modification: None,
+        protection: Default::default(),
+        decoded: vec![],
+    }
+}
+
+fn check_version(version: u32) -> Result<(), SnapshotError> {
+    if version == VERSION as u32 {
+        Ok(())
+    } else {
+        Err(SnapshotError::UnsupportedVersion(version))
+    }
+}
+
+fn write_unsigned(out: &mut Vec<u8>, mut value: u128) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_signed(out: &mut Vec<u8>, value: i128) {
+    write_unsigned(out, ((value << 1) ^ (value >> 127)) as u128)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        let (&byte, rest) = self
+            .data
+            .split_first()
+            .ok_or_else(|| corrupt("truncated"))?;
+        self.data = rest;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u128, SnapshotError> {
+        let mut value = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u128) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("varint too long"))
+    }
+
+    fn signed(&mut self) -> Result<i128, SnapshotError> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i128 ^ -((value & 1) as i128))
+    }
+
+    fn list(&mut self) -> Result<Vec<i128>, SnapshotError> {
+        let len = self.unsigned()?;
+        // every value takes at least one byte, which also stops a corrupt
+        // length from allocating a huge vector
+        if len > self.data.len() as u128 {
+            return Err(corrupt("truncated"));
+        }
+        (0..len).map(|_| self.signed()).collect()
+    }
+}
+
+fn from_binary(data: &[u8]) -> Result<Machine, SnapshotError> {
+    let mut reader = Reader { data };
+    check_version(reader.byte()? as u32)?;
+    let flags = reader.byte()?;
+    let arithmetic = ARITHMETICS
+        .get(((flags & ARITHMETIC_MASK) >> ARITHMETIC_SHIFT) as usize)
+        .ok_or_else(|| corrupt("bad arithmetic"))?
+        .0;
+    let mem_ptr =
+        usize::try_from(reader.unsigned()?).map_err(|_| corrupt("mem_ptr out of range"))?;
+    let relative_base =
+        isize::try_from(reader.signed()?).map_err(|_| corrupt("relative_base out of range"))?;
+    let memory = reader.list()?;
+    let input = reader.list()?;
+    let output = reader.list()?;
+    if !reader.data.is_empty() {
+        return Err(corrupt("trailing data"));
+    }
+    Ok(restore(
+        memory,
+        mem_ptr,
+        relative_base,
+        input,
+        output,
+        flags & FLAG_AWAIT_INPUT != 0,
+        arithmetic,
+    ))
+}
+
+fn from_text(text: &str) -> Result<Machine, SnapshotError> {
+    let mut lines = text.lines();
+    let version = lines
+        .next()
+        .and_then(|header| header.strip_prefix(TEXT_HEADER))
+        .and_then(|version| version.trim().parse::<u32>().ok())
+        .ok_or_else(|| corrupt("bad header"))?;
+    check_version(version)?;
+    let mut field = |key: &str| {
+        let line = lines
+            .next()
+            .ok_or_else(|| corrupt(format!("missing {}", key)))?;
+        let (found, value) = line.split_once(' ').unwrap_or((line, ""));
+        if found == key {
+            Ok(value.trim())
+        } else {
+            Err(corrupt(format!("expected {}, found {:?}", key, found)))
+        }
+    };
+    fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, SnapshotError> {
+        value
+            .parse()
+            .map_err(|_| corrupt(format!("bad {} {:?}", key, value)))
+    }
+    fn list(key: &str, value: &str) -> Result<Vec<i128>, SnapshotError> {
+        if value.is_empty() {
+            return Ok(vec![]);
+        }
+        value.split(',').map(|v| number(key, v.trim())).collect()
+    }
+    let mem_ptr = number("mem_ptr", field("mem_ptr")?)?;
+    let relative_base = number("relative_base", field("relative_base")?)?;
+    let await_empty_input = number("await_input", field("await_input")?)?;
+    let name = field("arithmetic")?;
+    let arithmetic = ARITHMETICS
+        .iter()
+        .find(|&&(_, n)| n == name)
+        .ok_or_else(|| corrupt(format!("bad arithmetic {:?}", name)))?
+        .0;
+    let memory = list("memory", field("memory")?)?;
+    let input = list("input", field("input")?)?;
+    let output = list("output", field("output")?)?;
+    Ok(restore(
+        memory,
+        mem_ptr,
+        relative_base,
+        input,
+        output,
+        await_empty_input,
+        arithmetic,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::int_code_machine::Status;
+
+    // reads two numbers, outputting each, then adds 1000 to the relative base
+    fn paused() -> Machine {
+        let mut machine = Machine::new("3,11,4,11,3,11,4,11,109,1000,99", vec![-7]);
+        machine.wait_on_input();
+        assert!(machine.run() == Status::Waiting);
+        machine.add_input(1 << 100);
+        machine.add_input(3);
+        machine
+    }
+
+    fn check_restored(mut restored: Machine) {
+        let mut original = paused();
+        assert!(restored.memory == original.memory);
+        assert!(restored.mem_ptr() == original.mem_ptr());
+        assert!(restored.input == vec![1 << 100, 3]);
+        assert!(restored.output == vec![-7]);
+        assert!(restored.run() == original.run());
+        assert!(restored.output == original.output);
+        assert!(restored.relative_base() == 1000);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut data = vec![];
+        paused().save(&mut data).unwrap();
+        assert!(data.starts_with(b"ICMS\x01\x01"));
+        check_restored(Machine::load(&data[..]).unwrap());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut data = vec![];
+        paused().save_as(&mut data, Encoding::Text).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.starts_with("intcode-snapshot 1\nmem_ptr 4\n"));
+        assert!(text.ends_with(&format!("input {},3\noutput -7\n", 1i128 << 100)));
+        check_restored(Machine::load(text.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_bad_snapshots() {
+        let mut data = vec![];
+        paused().save(&mut data).unwrap();
+        assert!(matches!(
+            Machine::load(&data[..data.len() - 1]),
+            Err(SnapshotError::Corrupt(_))
+        ));
+        data[4] = 9;
+        assert!(matches!(
+            Machine::load(&data[..]),
+            Err(SnapshotError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            Machine::load(&b"1,2,3"[..]),
+            Err(SnapshotError::UnknownFormat)
+        ));
+        let text = "intcode-snapshot 1\nmem_ptr 0\nrelative_base x\n";
+        assert!(matches!(
+            Machine::load(text.as_bytes()),
+            Err(SnapshotError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        for &encoding in &[Encoding::Binary, Encoding::Text] {
+            let mut machine = paused();
+            machine.set_arithmetic(Arithmetic::Saturating);
+            let mut data = vec![];
+            machine.save_as(&mut data, encoding).unwrap();
+            let restored = Machine::load(&data[..]).unwrap();
+            assert!(restored.arithmetic() == Arithmetic::Saturating);
+        }
+    }
+}
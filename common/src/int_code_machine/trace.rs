@@ -0,0 +1,172 @@
+use super::{Machine, MachineError, Status, Step, StepOutcome};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+#[derive(Debug)]
+pub enum TraceError {
+    Machine(MachineError),
+    Io(io::Error),
+}
+
+impl From<MachineError> for TraceError {
+    fn from(e: MachineError) -> Self {
+        TraceError::Machine(e)
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Machine(e) => write!(f, "{}", e),
+            TraceError::Io(e) => write!(f, "failed to write trace: {}", e),
+        }
+    }
+}
+
+impl Error for TraceError {}
+
+/// Runs a `Machine`, writing a JSON Lines record for every executed instruction.
+///
+/// Step numbers carry on across calls, so a single `Tracer` can follow a
+/// machine through several `Waiting` pauses.
+pub struct Tracer<W: Write> {
+    out: W,
+    steps: u64,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Tracer { out, steps: 0 }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// execute and trace a single instruction
+    pub fn step(&mut self, machine: &mut Machine) -> Result<StepOutcome, TraceError> {
+        let outcome = machine.step()?;
+        if let StepOutcome::Executed(step) = &outcome {
+            writeln!(self.out, "{}", record(self.steps, step))?;
+            self.steps += 1;
+        }
+        Ok(outcome)
+    }
+
+    /// trace `machine` until it halts or waits for input
+    pub fn run(&mut self, machine: &mut Machine) -> Result<Status, TraceError> {
+        loop {
+            match self.step(machine)? {
+                StepOutcome::Executed(_) => {}
+                StepOutcome::Waiting => return Ok(Status::Waiting),
+                StepOutcome::Halted => return Ok(Status::Halted),
+            }
+        }
+    }
+}
+
+impl Machine {
+    /// like `try_run`, but writes a trace of every instruction to `out`
+    pub fn run_traced<W: Write>(&mut self, out: W) -> Result<Status, TraceError> {
+        Tracer::new(out).run(self)
+    }
+}
+
+fn json_option(value: Option<i128>) -> String {
+    value.map_or_else(|| "null".to_owned(), |v| v.to_string())
+}
+
+/// a single trace line, e.g.
+/// `{"step":0,"address":0,"opcode":1002,"instruction":"MUL 4, #3, 4",...}`
+fn record(step_number: u64, step: &Step) -> String {
+    let operands = step
+        .operands()
+        .map(json_option)
+        .collect::<Vec<_>>()
+        .join(",");
+    let write = step.write.map_or_else(
+        || "null".to_owned(),
+        |w| {
+            format!(
+                r#"{{"address":{},"old":{},"new":{}}}"#,
+                w.address, w.old, w.new
+            )
+        },
+    );
+    let relative_base = step.relative_base.map_or_else(
+        || "null".to_owned(),
+        |(old, new)| format!(r#"{{"old":{},"new":{}}}"#, old, new),
+    );
+    // mnemonics and numbers never need escaping
+    format!(
+        concat!(
+            r#"{{"step":{},"address":{},"opcode":{},"instruction":"{}","operands":[{}],"#,
+            r#""write":{},"relative_base":{},"input":{},"output":{},"jumped":{}}}"#
+        ),
+        step_number,
+        step.address,
+        step.opcode,
+        step.instruction,
+        operands,
+        write,
+        relative_base,
+        json_option(step.input),
+        json_option(step.output),
+        step.jumped,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trace_records() {
+        let mut machine = Machine::new("3,11,109,5,1005,11,8,99,204,-5,99,0", vec![7]);
+        let mut out = vec![];
+        assert!(machine.run_traced(&mut out).unwrap() == Status::Halted);
+        let lines = String::from_utf8(out).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert!(lines.len() == 4);
+        assert!(
+            lines[0]
+                == concat!(
+                    r#"{"step":0,"address":0,"opcode":3,"instruction":"IN 11","operands":[11],"#,
+                    r#""write":{"address":11,"old":0,"new":7},"relative_base":null,"#,
+                    r#""input":7,"output":null,"jumped":false}"#
+                )
+        );
+        assert!(lines[1].contains(r#""relative_base":{"old":0,"new":5}"#));
+        assert!(lines[2].contains(r#""operands":[7,8],"#) && lines[2].contains(r#""jumped":true"#));
+        assert!(lines[3].starts_with(r#"{"step":3,"address":8,"#));
+        assert!(lines[3].contains(r#""operands":[3],"#) && lines[3].contains(r#""output":3,"#));
+    }
+
+    #[test]
+    fn test_trace_resumes() {
+        let mut machine = Machine::new("3,0,4,0,3,0,4,0,99", vec![1]);
+        machine.wait_on_input();
+        let mut tracer = Tracer::new(vec![]);
+        assert!(tracer.run(&mut machine).unwrap() == Status::Waiting);
+        machine.add_input(2);
+        assert!(tracer.run(&mut machine).unwrap() == Status::Halted);
+        assert!(tracer.steps() == 4);
+        let out = String::from_utf8(tracer.into_inner()).unwrap();
+        assert!(out
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with(r#"{"step":3,"address":6,"#));
+    }
+}
@@ -0,0 +1,558 @@
+//! Ahead-of-time translation of Intcode programs into Rust.
+//!
+//! `transpile` turns a program into Rust source for a module that exposes a
+//! `Machine` type, which runs like the interpreter's `Machine` but executes
+//! the program as native code. Every instruction reachable in the program's
+//! control flow graph becomes an arm of a `match` on the instruction pointer,
+//! e.g. for `ADD 224, #5, @3` at address 12
+//!
+//! ```text
+//! 12 => {
+//!     let a: i128 = cpu.read(224);
+//!     let b: i128 = 5;
+//!     cpu.write(cpu.relative(3)?, a.checked_add(b)?);
+//!     cpu.goto(16);
+//! }
+//! ```
+//!
+//! Whenever the compiled code can't carry on, it hands the instruction to
+//! the interpreter: I/O, jumps to addresses it didn't compile,
+//! instructions whose words have been written since the program was
+//! transpiled, and anything that would fail, like an overflow. So the
+//! results are always exactly those of the interpreter.
+//!
+//! The output is a module, not a crate of its own. A build script can
+//! transpile a program into `OUT_DIR`, to be included with
+//! `mod program { include!(concat!(env!("OUT_DIR"), "/program.rs")); }`
+//! by a crate depending on `common`, as day 2 does.
+
+use super::control_flow::control_flow_graph;
+use super::{Instruction, Machine, MachineError, Parameter, ParameterMode, Status, StepOutcome};
+use std::collections::BTreeMap;
+use std::convert::{Infallible, TryFrom};
+use std::fmt::Write as _;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+/// A program compiled by `transpile`
+pub trait Program {
+    /// the memory the program was transpiled from
+    const IMAGE: &'static [i128];
+    /// the address and size of each compiled instruction
+    const INSTRUCTIONS: &'static [(usize, usize)];
+
+    /// run compiled instructions until reaching one the interpreter has to
+    /// run, which is left at `cpu`'s instruction pointer
+    fn execute(cpu: &mut Cpu<'_>) -> Option<Infallible>;
+}
+
+/// Which compiled instructions no longer match memory
+#[derive(Clone)]
+struct Staleness {
+    /// the size of the compiled instruction at each address, or 0
+    sizes: Vec<u8>,
+    stale: Vec<bool>,
+}
+
+impl Staleness {
+    fn new(image: &[i128], instructions: &[(usize, usize)]) -> Self {
+        let mut sizes = vec![0; image.len()];
+        for &(address, size) in instructions {
+            sizes[address] = size as u8;
+        }
+        Staleness {
+            sizes,
+            stale: vec![false; image.len()],
+        }
+    }
+
+    /// compare every compiled instruction with `memory`, which may have been
+    /// patched since the last run
+    fn refresh(&mut self, memory: &[i128], image: &[i128], instructions: &[(usize, usize)]) {
+        for &(address, size) in instructions {
+            let words = address..address + size;
+            self.stale[address] = memory.get(words.clone()) != Some(&image[words]);
+        }
+    }
+
+    /// mark every compiled instruction covering `address` as stale
+    fn invalidate(&mut self, address: usize) {
+        if address >= self.sizes.len() {
+            return;
+        }
+        for start in address.saturating_sub(3)..=address {
+            if start + self.sizes[start] as usize > address {
+                self.stale[start] = true;
+            }
+        }
+    }
+}
+
+/// The registers and memory of a machine, as seen by compiled code
+pub struct Cpu<'a> {
+    memory: &'a mut Vec<i128>,
+    output: &'a mut Vec<i128>,
+    pc: usize,
+    relative_base: isize,
+    /// how many more instructions may run before returning to the interpreter
+    fuel: u64,
+    staleness: &'a mut Staleness,
+    halted: bool,
+}
+
+impl Cpu<'_> {
+    /// the address of the next instruction, or `None` if the compiled code
+    /// mustn't run it
+    #[inline]
+    pub fn fetch(&self) -> Option<usize> {
+        if self.fuel == 0 || self.staleness.stale.get(self.pc) == Some(&true) {
+            return None;
+        }
+        Some(self.pc)
+    }
+
+    /// finish an instruction, continuing at `address`
+    #[inline]
+    pub fn goto(&mut self, address: usize) {
+        self.pc = address;
+        self.fuel -= 1;
+    }
+
+    /// stop, as the program has halted
+    #[inline]
+    pub fn halt(&mut self) -> Option<Infallible> {
+        self.halted = true;
+        None
+    }
+
+    #[inline]
+    pub fn read(&self, address: usize) -> i128 {
+        self.memory.get(address).cloned().unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn write(&mut self, address: usize, value: i128) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        self.staleness.invalidate(address);
+    }
+
+    #[inline]
+    pub fn output(&mut self, value: i128) {
+        self.output.push(value);
+    }
+
+    /// the address of a relative parameter, if it's a valid one
+    #[inline]
+    pub fn relative(&self, offset: i128) -> Option<usize> {
+        let address = (self.relative_base as i128).checked_add(offset)?;
+        usize::try_from(address).ok()
+    }
+
+    /// the value of a jump target as an address, if it's a valid one
+    #[inline]
+    pub fn target(&self, value: i128) -> Option<usize> {
+        usize::try_from(value).ok()
+    }
+
+    /// `None`, leaving the relative base as it was, if it would overflow
+    #[inline]
+    pub fn adjust_relative_base(&mut self, by: i128) -> Option<()> {
+        let base = (self.relative_base as i128).checked_add(by)?;
+        self.relative_base = isize::try_from(base).ok()?;
+        Some(())
+    }
+}
+
+/// A `Machine` running the compiled program `P`. It dereferences to the
+/// underlying machine for everything but `run` and `try_run`, so input,
+/// output, memory and the rest work as usual.
+///
+/// Instruction budgets and deadlines are honoured. While the machine has
+/// hooks, write protection, a history or a self-modification watch, every
+/// instruction is interpreted, since those have to see each one.
+pub struct Transpiled<P> {
+    machine: Machine,
+    staleness: Staleness,
+    program: PhantomData<P>,
+}
+
+impl<P> Clone for Transpiled<P> {
+    fn clone(&self) -> Self {
+        Transpiled {
+            machine: self.machine.clone(),
+            staleness: self.staleness.clone(),
+            program: PhantomData,
+        }
+    }
+}
+
+impl<P: Program> Transpiled<P> {
+    pub fn new(input: Vec<i128>) -> Self {
+        Transpiled {
+            machine: Machine::from_words(P::IMAGE.to_vec(), input),
+            staleness: Staleness::new(P::IMAGE, P::INSTRUCTIONS),
+            program: PhantomData,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    pub fn run(&mut self) -> Status {
+        match self.try_run() {
+            Ok(status) => status,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// `Machine::try_run`, running compiled code wherever it can
+    pub fn try_run(&mut self) -> Result<Status, MachineError> {
+        self.staleness
+            .refresh(&self.machine.memory, P::IMAGE, P::INSTRUCTIONS);
+        loop {
+            if self.run_compiled() {
+                return Ok(Status::Halted);
+            }
+            if self.machine.out_of_budget() {
+                return Ok(Status::BudgetExhausted);
+            }
+            match self.machine.step()? {
+                StepOutcome::Executed(step) => {
+                    if let Some(write) = step.write {
+                        self.staleness.invalidate(write.address);
+                    }
+                    self.machine.spend_budget();
+                }
+                StepOutcome::Waiting => return Ok(Status::Waiting),
+                StepOutcome::Halted => return Ok(Status::Halted),
+            }
+        }
+    }
+
+    /// run compiled code until it stops, returning whether it halted
+    fn run_compiled(&mut self) -> bool {
+        let machine = &mut self.machine;
+        let watched = !machine.hooks.is_empty()
+            || !machine.protection.is_empty()
+            || machine.history.is_some()
+            || machine.self_modification.is_some();
+        let mut fuel = machine.instruction_budget.unwrap_or(u64::MAX);
+        if machine.deadline.is_some() {
+            fuel = fuel.min(machine.until_clock_check as u64);
+        }
+        if watched || fuel == 0 {
+            return false;
+        }
+        let mut cpu = Cpu {
+            memory: &mut machine.memory,
+            output: &mut machine.output,
+            pc: machine.mem_ptr,
+            relative_base: machine.relative_base,
+            fuel,
+            staleness: &mut self.staleness,
+            halted: false,
+        };
+        P::execute(&mut cpu);
+        let spent = fuel - cpu.fuel;
+        machine.mem_ptr = cpu.pc;
+        machine.relative_base = cpu.relative_base;
+        if let Some(budget) = &mut machine.instruction_budget {
+            *budget -= spent;
+        }
+        if machine.deadline.is_some() {
+            machine.until_clock_check -= spent as u32;
+        }
+        cpu.halted
+    }
+}
+
+impl<P> Deref for Transpiled<P> {
+    type Target = Machine;
+
+    fn deref(&self) -> &Machine {
+        &self.machine
+    }
+}
+
+impl<P> DerefMut for Transpiled<P> {
+    fn deref_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+}
+
+/// the largest positional address compiled; anything further is left to
+/// the interpreter, so the generated code builds for 32-bit targets too
+const MAX_ADDRESS: i128 = u32::MAX as i128;
+
+/// an expression for the value of an input parameter
+fn operand(parameter: &Parameter) -> Option<String> {
+    match parameter.mode {
+        ParameterMode::Immediate => Some(parameter.value.to_string()),
+        ParameterMode::Positional if (0..=MAX_ADDRESS).contains(&parameter.value) => {
+            Some(format!("cpu.read({})", parameter.value))
+        }
+        ParameterMode::Positional => None,
+        ParameterMode::Relative => Some(format!("cpu.read(cpu.relative({})?)", parameter.value)),
+    }
+}
+
+/// an expression for the address of a destination parameter
+fn destination(parameter: &Parameter) -> Option<String> {
+    match parameter.mode {
+        ParameterMode::Immediate => None,
+        ParameterMode::Positional if (0..=MAX_ADDRESS).contains(&parameter.value) => {
+            Some(parameter.value.to_string())
+        }
+        ParameterMode::Positional => None,
+        ParameterMode::Relative => Some(format!("cpu.relative({})?", parameter.value)),
+    }
+}
+
+/// the body of the match arm for `instruction`, or `None` if it's left to
+/// the interpreter
+fn arm(address: usize, instruction: &Instruction) -> Option<String> {
+    use Instruction::*;
+    let next = address + instruction.size();
+    let mut body = String::new();
+    let mut operands = |names: &[&str], parameters: &[&Parameter]| -> Option<()> {
+        for (name, parameter) in names.iter().zip(parameters) {
+            body += &format!("let {}: i128 = {};\n", name, operand(parameter)?);
+        }
+        Some(())
+    };
+    match instruction {
+        Add(a, b, dest) | Mult(a, b, dest) | LessThan(a, b, dest) | Equal(a, b, dest) => {
+            operands(&["a", "b"], &[a, b])?;
+            let value = match instruction {
+                Add(..) => "a.checked_add(b)?",
+                Mult(..) => "a.checked_mul(b)?",
+                LessThan(..) => "i128::from(a < b)",
+                _ => "i128::from(a == b)",
+            };
+            body += &format!("cpu.write({}, {});\n", destination(dest)?, value);
+            body += &format!("cpu.goto({});\n", next);
+        }
+        Output(a) => {
+            operands(&["a"], &[a])?;
+            body += &format!("cpu.output(a);\ncpu.goto({});\n", next);
+        }
+        JumpTrue(check, target) | JumpFalse(check, target) => {
+            operands(&["check", "target"], &[check, target])?;
+            let taken = match instruction {
+                JumpTrue(..) => "check != 0",
+                _ => "check == 0",
+            };
+            body += &format!(
+                "if {} {{\n    cpu.goto(cpu.target(target)?);\n}} else {{\n    cpu.goto({});\n}}\n",
+                taken, next
+            );
+        }
+        AdjustRelativeBase(a) => {
+            operands(&["a"], &[a])?;
+            body += &format!("cpu.adjust_relative_base(a)?;\ncpu.goto({});\n", next);
+        }
+        Halt => body += "return cpu.halt();\n",
+        Input(_) => return None,
+    }
+    Some(body)
+}
+
+/// Translate the program in `memory` into the Rust source of a module
+/// defining `Code`, an implementation of `Program`, and `Machine`, the
+/// `Transpiled` machine running it. The module refers to this one as
+/// `common::int_code_machine::transpiler`.
+pub fn transpile(memory: &[i128]) -> String {
+    let mut instructions = BTreeMap::new();
+    for block in control_flow_graph(memory).blocks.values() {
+        for &(address, instruction) in &block.instructions {
+            // an instruction running off the end of memory is left to the
+            // interpreter, as it reads the zeroes past it
+            if address + instruction.size() <= memory.len() {
+                instructions.insert(address, instruction);
+            }
+        }
+    }
+
+    let mut arms = String::new();
+    let mut compiled = vec![];
+    for (&address, instruction) in &instructions {
+        if let Some(body) = arm(address, instruction) {
+            compiled.push(format!("({}, {})", address, instruction.size()));
+            // writing to a String can't fail
+            writeln!(arms, "                {} => {{", address).unwrap();
+            for line in body.lines() {
+                writeln!(arms, "                    {}", line).unwrap();
+            }
+            writeln!(arms, "                }}").unwrap();
+        }
+    }
+    let image = memory
+        .iter()
+        .map(|word| word.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "// Generated by common::int_code_machine::transpiler::transpile; don't edit.
+
+use common::int_code_machine::transpiler::{{Cpu, Program, Transpiled}};
+use std::convert::Infallible;
+
+/// The transpiled program
+pub struct Code;
+
+/// A machine running the transpiled program
+pub type Machine = Transpiled<Code>;
+
+impl Program for Code {{
+    const IMAGE: &'static [i128] = &[{}];
+    const INSTRUCTIONS: &'static [(usize, usize)] = &[{}];
+
+    fn execute(cpu: &mut Cpu<'_>) -> Option<Infallible> {{
+        loop {{
+            match cpu.fetch()? {{
+{}                _ => return None,
+            }}
+        }}
+    }}
+}}
+",
+        image,
+        compiled.join(", "),
+        arms
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // counts down from its first word's second parameter to 0, outputting
+    // each number, then halts
+    const COUNTDOWN: &str = "1101,0,3,100,4,100,1001,100,-1,100,1005,100,4,99";
+
+    /// what `transpile` generates for `COUNTDOWN`
+    struct Countdown;
+
+    impl Program for Countdown {
+        const IMAGE: &'static [i128] = &[
+            1101, 0, 3, 100, 4, 100, 1001, 100, -1, 100, 1005, 100, 4, 99,
+        ];
+        const INSTRUCTIONS: &'static [(usize, usize)] = &[(0, 4), (4, 2), (6, 4), (10, 3), (13, 1)];
+
+        fn execute(cpu: &mut Cpu<'_>) -> Option<Infallible> {
+            loop {
+                match cpu.fetch()? {
+                    0 => {
+                        let a: i128 = 0;
+                        let b: i128 = 3;
+                        cpu.write(100, a.checked_add(b)?);
+                        cpu.goto(4);
+                    }
+                    4 => {
+                        let a: i128 = cpu.read(100);
+                        cpu.output(a);
+                        cpu.goto(6);
+                    }
+                    6 => {
+                        let a: i128 = cpu.read(100);
+                        let b: i128 = -1;
+                        cpu.write(100, a.checked_add(b)?);
+                        cpu.goto(10);
+                    }
+                    10 => {
+                        let check: i128 = cpu.read(100);
+                        let target: i128 = 4;
+                        if check != 0 {
+                            cpu.goto(cpu.target(target)?);
+                        } else {
+                            cpu.goto(13);
+                        }
+                    }
+                    13 => {
+                        return cpu.halt();
+                    }
+                    _ => return None,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_transpile() {
+        let memory = Machine::new(COUNTDOWN, vec![]).memory;
+        let code = transpile(&memory);
+        assert!(code.contains("= &[(0, 4), (4, 2), (6, 4), (10, 3), (13, 1)];"));
+        let arms = code
+            .lines()
+            .skip_while(|line| !line.contains("match cpu.fetch()?"))
+            .skip(1)
+            .take_while(|line| !line.contains("_ => return None"))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        assert!(arms.len() == 29);
+        assert!(arms[0] == "0 => {" && arms[3] == "cpu.write(100, a.checked_add(b)?);");
+        assert!(
+            arms[20..24]
+                == [
+                    "if check != 0 {",
+                    "cpu.goto(cpu.target(target)?);",
+                    "} else {",
+                    "cpu.goto(13);"
+                ]
+        );
+
+        let code = transpile(&Machine::new("109,-5,203,2,21101,1,2,7,99", vec![]).memory);
+        assert!(code.contains("cpu.adjust_relative_base(a)?;"));
+        assert!(code.contains("cpu.write(cpu.relative(7)?, a.checked_add(b)?);"));
+        assert!(code.contains("                8 => {\n                    return cpu.halt();\n"));
+        // the input is left to the interpreter
+        assert!(!code.contains("                2 => {"));
+    }
+
+    #[test]
+    fn test_run() {
+        let mut machine = Transpiled::<Countdown>::new(vec![]);
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![3, 2, 1]);
+
+        // patching the program before it runs is picked up
+        let mut machine = Transpiled::<Countdown>::new(vec![]);
+        machine.memory[2] = 5;
+        machine.run();
+        assert!(machine.output == vec![5, 4, 3, 2, 1]);
+
+        // as is the program patching itself: here the first instruction is
+        // changed to write a halt over the loop's jump
+        let mut machine = Transpiled::<Countdown>::new(vec![]);
+        machine.memory[1] = 99;
+        machine.memory[2] = 0;
+        machine.memory[3] = 10;
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![0] && machine.memory[100] == -1);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut machine = Transpiled::<Countdown>::new(vec![]);
+        machine.set_instruction_budget(Some(5));
+        assert!(machine.run() == Status::BudgetExhausted);
+        assert!(machine.output == vec![3, 2] && machine.mem_ptr() == 6);
+        machine.set_instruction_budget(None);
+        assert!(machine.run() == Status::Halted);
+
+        // a watched machine is interpreted, with the same result
+        let mut machine = Transpiled::<Countdown>::new(vec![]);
+        machine.record_history(100);
+        machine.run();
+        assert!(machine.output == vec![3, 2, 1] && machine.history_len() == 10);
+    }
+}
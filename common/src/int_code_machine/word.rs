@@ -0,0 +1,267 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a `Machine` handles arithmetic that overflows its word type, in `Add`
+/// and `Mult` as well as when `AdjustRelativeBase` takes the relative base
+/// out of the range of an `isize`. Debug and release builds behave the same.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Arithmetic {
+    /// stop with `MachineError::Overflow`
+    #[default]
+    Checked,
+    /// wrap around, two's complement style
+    Wrapping,
+    /// clamp to the largest or smallest value
+    Saturating,
+}
+
+/// A value a `Machine` can hold in memory and pass through its I/O.
+///
+/// `i128` is the default, `i64` is smaller and faster for programs known to
+/// stay in range, and with the `bigint` feature `num_bigint::BigInt` never
+/// overflows at all, whatever the `Arithmetic`.
+pub trait Word: Clone + fmt::Debug + fmt::Display + Eq + Ord + FromStr + Send + 'static {
+    fn zero() -> Self;
+
+    fn one() -> Self;
+
+    fn from_isize(value: isize) -> Self;
+
+    fn from_usize(value: usize) -> Self;
+
+    fn to_u32(&self) -> Option<u32>;
+
+    fn to_isize(&self) -> Option<isize>;
+
+    /// the relative base this word would give, or `None` if it's out of
+    /// range under `Arithmetic::Checked`
+    fn to_isize_with(&self, arithmetic: Arithmetic) -> Option<isize>;
+
+    /// `self + other`, or `None` if it overflows under `Arithmetic::Checked`
+    fn add(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    /// `self * other`, or `None` if it overflows under `Arithmetic::Checked`
+    fn mul(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+
+    /// the memory address this word refers to, if it's a valid one
+    fn to_address(&self) -> Option<usize> {
+        self.to_isize()
+            .and_then(|address| usize::try_from(address).ok())
+    }
+}
+
+macro_rules! primitive_word {
+    ($($t:ty),*) => {
+        $(
+            impl Word for $t {
+                fn zero() -> Self {
+                    0
+                }
+
+                fn one() -> Self {
+                    1
+                }
+
+                fn from_isize(value: isize) -> Self {
+                    value as $t
+                }
+
+                fn from_usize(value: usize) -> Self {
+                    value as $t
+                }
+
+                fn to_u32(&self) -> Option<u32> {
+                    u32::try_from(*self).ok()
+                }
+
+                fn to_isize(&self) -> Option<isize> {
+                    isize::try_from(*self).ok()
+                }
+
+                fn to_isize_with(&self, arithmetic: Arithmetic) -> Option<isize> {
+                    match arithmetic {
+                        Arithmetic::Checked => self.to_isize(),
+                        Arithmetic::Wrapping => Some(*self as isize),
+                        Arithmetic::Saturating => Some(
+                            self.to_isize()
+                                .unwrap_or(if *self < 0 { isize::MIN } else { isize::MAX }),
+                        ),
+                    }
+                }
+
+                fn add(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                    match arithmetic {
+                        Arithmetic::Checked => self.checked_add(*other),
+                        Arithmetic::Wrapping => Some(self.wrapping_add(*other)),
+                        Arithmetic::Saturating => Some(self.saturating_add(*other)),
+                    }
+                }
+
+                fn mul(&self, other: &Self, arithmetic: Arithmetic) -> Option<Self> {
+                    match arithmetic {
+                        Arithmetic::Checked => self.checked_mul(*other),
+                        Arithmetic::Wrapping => Some(self.wrapping_mul(*other)),
+                        Arithmetic::Saturating => Some(self.saturating_mul(*other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+primitive_word!(i64, i128);
+
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn zero() -> Self {
+        0.into()
+    }
+
+    fn one() -> Self {
+        1.into()
+    }
+
+    fn from_isize(value: isize) -> Self {
+        value.into()
+    }
+
+    fn from_usize(value: usize) -> Self {
+        value.into()
+    }
+
+    fn to_u32(&self) -> Option<u32> {
+        u32::try_from(self).ok()
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        isize::try_from(self).ok()
+    }
+
+    fn to_isize_with(&self, arithmetic: Arithmetic) -> Option<isize> {
+        use num_bigint::Sign;
+        match arithmetic {
+            Arithmetic::Checked => self.to_isize(),
+            Arithmetic::Wrapping => {
+                // truncate to the low 128 bits, then on to an isize
+                let fill = if self.sign() == Sign::Minus { 0xff } else { 0 };
+                let mut low = [fill; 16];
+                for (byte, &b) in low.iter_mut().zip(self.to_signed_bytes_le().iter()) {
+                    *byte = b;
+                }
+                Some(i128::from_le_bytes(low) as isize)
+            }
+            Arithmetic::Saturating => {
+                Some(self.to_isize().unwrap_or(if self.sign() == Sign::Minus {
+                    isize::MIN
+                } else {
+                    isize::MAX
+                }))
+            }
+        }
+    }
+
+    fn add(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn mul(&self, other: &Self, _: Arithmetic) -> Option<Self> {
+        Some(self * other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::int_code_machine::{Arithmetic, Machine, MachineError, Status};
+
+    // outputs the square of its input
+    const SQUARE: &str = "3,9,2,9,9,9,4,9,99,0";
+
+    #[test]
+    fn test_i64_machine() {
+        let mut machine: Machine<Vec<i64>> = Machine::with_memory(SQUARE, vec![3_000_000_000]);
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![9_000_000_000_000_000_000]);
+        assert!(std::mem::size_of_val(&machine.memory[0]) == 8);
+    }
+
+    #[test]
+    fn test_wide_opcode() {
+        // an opcode too wide for a u32 decodes through its digits: this is
+        // ADD 0, 0, 0 with a stray mode digit far past the third parameter
+        let src = "100000000001,0,0,0,99";
+        let mut machine: Machine<Vec<i64>> = Machine::with_memory(src, vec![]);
+        machine.run();
+        assert!(machine.memory[0] == 200000000002);
+
+        let mut machine = Machine::new("300000000001,0,0,0,99", vec![]);
+        assert!(
+            machine.try_run()
+                == Err(MachineError::InvalidParameterMode {
+                    address: 0,
+                    opcode: 300000000001,
+                    mode: 3,
+                })
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let run = |arithmetic, input| {
+            let mut machine: Machine<Vec<i64>> = Machine::with_memory(SQUARE, vec![input]);
+            machine.set_arithmetic(arithmetic);
+            machine.try_run().map(|_| machine.output[0])
+        };
+        let big = 1 << 32;
+        assert!(
+            run(Arithmetic::Checked, big)
+                == Err(MachineError::Overflow {
+                    address: 2,
+                    opcode: 2,
+                })
+        );
+        assert!(run(Arithmetic::Wrapping, big) == Ok(0));
+        assert!(run(Arithmetic::Saturating, big) == Ok(i64::MAX));
+        assert!(run(Arithmetic::Saturating, -big) == Ok(i64::MAX));
+        assert!(run(Arithmetic::Checked, 3) == Ok(9));
+    }
+
+    #[test]
+    fn test_relative_base_overflow() {
+        // adjusts the relative base by i128::MAX twice, then outputs 1
+        let src = "109,170141183460469231731687303715884105727,\
+                   109,170141183460469231731687303715884105727,104,1,99";
+        let mut machine = Machine::new(src, vec![]);
+        assert!(
+            machine.try_run()
+                == Err(MachineError::Overflow {
+                    address: 0,
+                    opcode: 109,
+                })
+        );
+
+        let mut machine = Machine::new(src, vec![]);
+        machine.set_arithmetic(Arithmetic::Saturating);
+        assert!(machine.try_run() == Ok(Status::Halted));
+        assert!(machine.relative_base == isize::MAX);
+
+        let mut machine = Machine::new(src, vec![]);
+        machine.set_arithmetic(Arithmetic::Wrapping);
+        assert!(machine.try_run() == Ok(Status::Halted));
+        assert!(machine.relative_base == -2);
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_bigint_machine() {
+        use num_bigint::BigInt;
+        let big = BigInt::from(u128::MAX);
+        let mut machine: Machine<Vec<BigInt>> = Machine::with_memory(SQUARE, vec![big.clone()]);
+        assert!(machine.run() == Status::Halted);
+        assert!(machine.output == vec![&big * &big]);
+    }
+}
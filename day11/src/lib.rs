@@ -1,4 +1,4 @@
-use common::int_code_machine::Machine;
+use common::int_code_machine::{Machine, Packet};
 use std::collections::HashSet;
 
 pub fn get_parsed_input()-> String {
@@ -153,13 +153,12 @@ fn run_robot(input: &str, start_color: PanelColor)-> PaintedPanelDescription {
     robot.wait_on_input();
     let mut robot_position = (0i128, 0i128);
     let mut current_facing = RobotFacing::Up;
-    robot.run();
-    while !robot.output.is_empty() {
-        if robot.output.len() < 2 {
-            panic!("Robot didn't output enough actions!")
-        }
-        let direction = robot.output.pop().unwrap();
-        let color = PanelColor::from_i128(robot.output.pop().unwrap());
+    loop {
+        let (color, direction) = match robot.next_packet() {
+            Packet::Complete([color, direction]) => (PanelColor::from_i128(color), direction),
+            Packet::Stopped(_, partial) if partial.is_empty() => break,
+            Packet::Stopped(..) => panic!("Robot didn't output enough actions!"),
+        };
         match (color, panels.white_panels.contains(&robot_position)) {
             (PanelColor::Black, true) => {
                 panels.white_panels.remove(&robot_position);
@@ -180,7 +179,6 @@ fn run_robot(input: &str, start_color: PanelColor)-> PaintedPanelDescription {
             PanelColor::Black.to_i128()
         };
         robot.add_input(next_input);
-        robot.run();
     }
     panels
 }
@@ -1,3 +1,5 @@
+use common::int_code_machine::profile::{Profile, Profiler};
+use common::int_code_machine::session::{Session, SessionRecorder};
 use common::int_code_machine::{Machine, Status};
 use common::iter_tools::*;
 use std::fmt;
@@ -198,12 +200,37 @@ fn get_line() -> String {
 }
 
 pub fn part2(src: &String) {
+    println!("Part 2 = {}", final_score(src));
+}
+
+// play the whole game, returning the score once every block is broken
+pub fn final_score(src: &str) -> i128 {
+    play(src, Machine::run)
+}
+
+// play the whole game, returning its execution profile rather than the score
+pub fn profile_game(src: &str) -> Profile {
+    let mut profiler = Profiler::new();
+    play(src, |machine| profiler.run(machine).unwrap());
+    profiler.into_profile()
+}
+
+// play the whole game, recording its I/O so it can be replayed as a check
+pub fn record_game(src: &str) -> Session {
+    let mut recorder = SessionRecorder::new();
+    play(src, |machine| recorder.run(machine).unwrap());
+    recorder.into_session()
+}
+
+// play the game to the end using `run` to run the machine, returning the final
+// score
+fn play(src: &str, mut run: impl FnMut(&mut Machine) -> Status) -> i128 {
     let mut machine = Machine::new(src, vec![]);
     machine.memory[0] = 2;
     machine.wait_on_input();
 
     // get the initial canvas
-    machine.run();
+    run(&mut machine);
     let mut max_x = 0usize;
     let mut max_y = 0usize;
     let mut score = 0;
@@ -241,12 +268,12 @@ pub fn part2(src: &String) {
     game.score = score;
 
     machine.add_input(game.determine_input().to_i128());
-    while let Status::Waiting = machine.run() {
+    while let Status::Waiting = run(&mut machine) {
         process_output(&mut machine, &mut game);
         machine.add_input(game.determine_input().to_i128());
     }
     process_output(&mut machine, &mut game);
-    println!("Part 2 = {}", game.score);
+    game.score
 }
 
 fn process_output(machine: &mut Machine, game: &mut ArcadeGame) {
@@ -267,3 +294,28 @@ fn process_output(machine: &mut Machine, game: &mut ArcadeGame) {
     }
     machine.output.clear();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replay_game() {
+        let src = get_parsed_input();
+        let session = record_game(&src);
+        let mut saved = vec![];
+        session.save(&mut saved).unwrap();
+        let session = Session::load(&saved[..]).unwrap();
+
+        let mut machine = Machine::new(&src, vec![]);
+        machine.memory[0] = 2;
+        assert!(session.replay(&mut machine).unwrap() == Status::Halted);
+        // the score is drawn at x = -1, y = 0, last when the final block breaks
+        let score = machine
+            .output
+            .chunks(3)
+            .rfind(|triple| triple[0] == -1 && triple[1] == 0)
+            .unwrap()[2];
+        assert!(score == final_score(&src));
+    }
+}
@@ -0,0 +1,15 @@
+//! Transpiles the bundled input into Rust, for `with_first_registers_transpiled`
+use common::int_code_machine::{transpiler, Machine};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const INPUT: &str = "src/input/input1";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", INPUT);
+    let src = fs::read_to_string(INPUT).expect("failed to read the input");
+    let memory = Machine::new(&src, vec![]).memory;
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("program.rs");
+    fs::write(out, transpiler::transpile(&memory)).expect("failed to write the program");
+}
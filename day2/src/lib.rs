@@ -1,4 +1,10 @@
-use common::int_code_machine::Machine;
+use common::int_code_machine::{Machine, Status, Word};
+use common::int_code_machine::transpiler::Program;
+
+// the bundled input, transpiled to Rust by build.rs
+mod program {
+    include!(concat!(env!("OUT_DIR"), "/program.rs"));
+}
 
 pub fn get_parsed_input()-> String {
     String::from(include_str!("input/input1"))
@@ -6,15 +12,22 @@ pub fn get_parsed_input()-> String {
 
 // part 1 -- what is the value in register 0 when register 1 = 12 and register 2 = 2
 pub fn part1(src: &String) {
-    println!("Part 1 = {}", with_first_registers(src, 12, 2));
+    println!("Part 1 = {}", with_first_registers::<i128>(src, 12, 2).unwrap());
 }
 
 // part 2 -- what values of r1 and r2 results in r0 == 19690720?
 pub fn part2(src: &String) {
     let target = 19690720;
+    // the transpiled program only stands in for the input it was built from
+    let transpiled = Machine::new(src, vec![]).memory == program::Code::IMAGE;
     for i1 in 0..100 {
         for i2 in 0..100 {
-            if with_first_registers(src, i1, i2) == target {
+            let result = if transpiled {
+                with_first_registers_transpiled(i1, i2)
+            } else {
+                with_first_registers::<i128>(src, i1, i2)
+            };
+            if result == Some(target) {
                 println!("Part 2 = {}", 100 * i1 + i2);
                 return;
             }
@@ -22,12 +35,55 @@ pub fn part2(src: &String) {
     }
 }
 
+// no sensible noun/verb pair runs anywhere near this long, so a pair that
+// does is stuck in a loop
+const INSTRUCTION_BUDGET: u64 = 100_000;
+
 // parse the machine from `src`, set registers r1 and r2, run and return the
-// resulting register 0 value
-pub fn with_first_registers(machine_src: &str, r1: i128, r2: i128) -> i128 {
-    let mut machine = Machine::new(machine_src, vec![]);
+// resulting register 0 value, or None if the program fails or doesn't halt
+pub fn with_first_registers<W: Word>(machine_src: &str, r1: W, r2: W) -> Option<W> {
+    let mut machine: Machine<Vec<W>> = Machine::with_memory(machine_src, vec![]);
+    machine.memory[1] = r1;
+    machine.memory[2] = r2;
+    machine.set_instruction_budget(Some(INSTRUCTION_BUDGET));
+    match machine.try_run() {
+        Ok(Status::Halted) => Some(machine.memory[0].clone()),
+        _ => None,
+    }
+}
+
+// `with_first_registers` for the bundled input, running the program
+// transpiled by build.rs rather than parsing and interpreting it
+pub fn with_first_registers_transpiled(r1: i128, r2: i128) -> Option<i128> {
+    let mut machine = program::Machine::new(vec![]);
     machine.memory[1] = r1;
     machine.memory[2] = r2;
-    machine.run();
-    machine.memory[0]
+    machine.set_instruction_budget(Some(INSTRUCTION_BUDGET));
+    match machine.try_run() {
+        Ok(Status::Halted) => Some(machine.memory[0]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transpiled_matches_interpreter() {
+        let src = get_parsed_input();
+        let agree = |noun: i128, verb: i128| {
+            let transpiled = with_first_registers_transpiled(noun, verb);
+            assert!(transpiled == with_first_registers::<i128>(&src, noun, verb));
+            transpiled
+        };
+        // the part 1 and part 2 answers
+        assert!(agree(12, 2) == Some(4090701));
+        assert!(agree(64, 21) == Some(19690720));
+        for noun in (0..100).step_by(9) {
+            for verb in 0..100 {
+                agree(noun, verb);
+            }
+        }
+    }
 }
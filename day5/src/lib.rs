@@ -16,3 +16,51 @@ pub fn part2(src: &String) {
     machine.run();
     println!("Part 2 = {:?}", machine.output);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::int_code_machine::coverage::Coverage;
+    use common::int_code_machine::Instruction;
+
+    // the coverage of the diagnostic program over a run on each of `inputs`,
+    // e.g. for `coverage(src, &[1, 5]).annotate(&Machine::new(src, vec![]).memory)`
+    fn coverage(src: &str, inputs: &[i128]) -> Coverage {
+        let mut coverage = Coverage::new();
+        for &input in inputs {
+            let mut machine = Machine::new(src, vec![input]);
+            machine.run_covered(&mut coverage).unwrap();
+        }
+        coverage
+    }
+
+    // the mnemonics of the instructions executed, as they are in `memory`
+    fn executed(coverage: &Coverage, memory: &[i128]) -> Vec<&'static str> {
+        coverage
+            .executed
+            .keys()
+            // the program writes some of its instructions as it runs
+            .filter_map(|&address| Instruction::decode(&memory[address..], address).ok())
+            .map(|instruction| instruction.mnemonic())
+            .collect()
+    }
+
+    #[test]
+    fn test_coverage() {
+        let src = get_parsed_input();
+        let memory = Machine::new(&src, vec![]).memory;
+        let part1 = coverage(&src, &[1]);
+        let part2 = coverage(&src, &[5]);
+        let both = coverage(&src, &[1, 5]);
+        // only part 2's checks jump and compare
+        let branches = ["JT", "JF", "LT", "EQ"];
+        assert!(!executed(&part1, &memory).iter().any(|m| branches.contains(m)));
+        assert!(branches
+            .iter()
+            .all(|m| executed(&part2, &memory).contains(m)));
+        let mut merged = part1.clone();
+        merged.merge(&part2);
+        assert!(merged == both);
+        assert!(both.instruction_coverage(&memory) > part2.instruction_coverage(&memory));
+    }
+}
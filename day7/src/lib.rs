@@ -1,6 +1,6 @@
-use common::int_code_machine::{Machine, Status};
+use common::int_code_machine::network::Network;
+use common::int_code_machine::Machine;
 use common::permutations::*;
-use std::mem;
 
 pub fn get_parsed_input()-> Machine {
     let input = include_str!("input/input");
@@ -37,29 +37,10 @@ pub fn part2(start_machine: &Machine) {
     let result = s
         .permutations()
         .fold(None, |acc, perm| {
-            let init = init_machines(&start_machine, perm);
-            let mut machines = init.into_iter().map(|m| Some(m)).collect::<Vec<_>>();
-            let mut current = 0;
-            let mut prev_output = *machines[machines.len() - 1]
-                .as_ref()
-                .and_then(|a| a.output.last())
-                .unwrap();
-            loop {
-                let mut machine = match mem::replace(&mut machines[current], None) {
-                    None => break,
-                    Some(m) => m,
-                };
-                machine.add_input(prev_output);
-                let run_status = machine.run();
-                prev_output = *machine.output.last().unwrap();
-                if let Status::Waiting = run_status {
-                    machines[current] = Some(machine);
-                }
-                current = (current + 1) % machines.len();
-            }
+            let output = run_feedback_loop(start_machine, perm);
             match acc {
-                Some(max) => Some(if max > prev_output { max } else { prev_output }),
-                _ => Some(prev_output),
+                Some(max) => Some(if max > output { max } else { output }),
+                _ => Some(output),
             }
         })
         .unwrap();
@@ -67,18 +48,22 @@ pub fn part2(start_machine: &Machine) {
     println!("Part 2 = {}", result)
 }
 
-fn init_machines(src_machine: &Machine, settings: &Vec<i128>) -> Vec<Machine> {
-    let mut machines = Vec::with_capacity(settings.len());
-    let mut prev_output = 0;
-
-    for setting in settings {
-        let mut machine = src_machine.clone();
-        machine.wait_on_input();
-        machine.input = vec![*setting, prev_output];
-        machine.run();
-        prev_output = machine.output[0];
-        machines.push(machine)
+/// run one amplifier per setting on its own thread, each feeding the next and
+/// the last feeding back into the first, and return the last one's final output
+fn run_feedback_loop(src_machine: &Machine, settings: &[i128]) -> i128 {
+    let mut network = Network::new();
+    let amplifiers = settings
+        .iter()
+        .map(|&setting| {
+            let mut machine = src_machine.clone();
+            machine.input = vec![setting];
+            network.add(machine)
+        })
+        .collect::<Vec<_>>();
+    for (i, &amplifier) in amplifiers.iter().enumerate() {
+        network.connect(amplifier, amplifiers[(i + 1) % amplifiers.len()]);
     }
-
-    machines
+    network.input(amplifiers[0]).send(0).unwrap();
+    let finished = network.run();
+    *finished.last().unwrap().machine.output.last().unwrap()
 }
@@ -1,19 +1,22 @@
-use common::int_code_machine::Machine;
+use common::int_code_machine::{Machine, Memory};
 
 pub fn get_parsed_input() -> Machine {
     Machine::new(include_str!("input/input"), vec![])
 }
 
 pub fn part1(input: &Machine) {
-    let mut machine = input.clone();
-    machine.add_input(1);
-    machine.run();
-    println!("Part 1 = {:?}", machine.output.last().unwrap());
+    println!("Part 1 = {:?}", run_boost(input, 1));
 }
 
 pub fn part2(input: &Machine) {
+    println!("Part 2 = {:?}", run_boost(input, 2));
+}
+
+// run the BOOST program in `mode` (1 = test, 2 = sensor boost) and return
+// its final output
+pub fn run_boost<M: Memory>(input: &Machine<M>, mode: M::Word) -> M::Word {
     let mut machine = input.clone();
-    machine.add_input(2);
+    machine.add_input(mode);
     machine.run();
-    println!("Part 2 = {:?}", machine.output.last().unwrap());
+    machine.output.pop().unwrap()
 }